@@ -0,0 +1,67 @@
+//! Compares copying frames in and out of `Vec`s with sharing bodies through `Bytes`,
+//! for a 4 MB message batch. The `vec` baselines redo what `Command::encode` and
+//! `Command::decode` did while bodies were `Vec<u8>`.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use grocketmq_remoting::common::{code::RequestCode, command::Command};
+
+const BODY_SIZE: usize = 4 * 1024 * 1024;
+
+fn command() -> Command {
+    let mut command = Command::new(RequestCode::SendBatchMessage);
+    command.add_property("topic", "TopicTest");
+    command.set_body(vec![0x5a; BODY_SIZE]);
+    command
+}
+
+/// Encodes like before bodies were `Bytes`: the body is copied once with the command,
+/// which encoding consumed, and once more into the frame.
+fn encode_vec(command: &Command, body: &[u8]) -> Vec<u8> {
+    let body = body.to_vec();
+    let mut header = BytesMut::new();
+    command.encode_header(&mut header);
+    let mut frame = Vec::with_capacity(header.len() + body.len());
+    frame.extend_from_slice(&header);
+    frame.extend(body);
+    frame
+}
+
+/// Decodes like before bodies were `Bytes`: the body is copied out of the frame into a
+/// `Vec` of its own.
+fn decode_vec(frame: &Bytes) -> (Command, Option<Vec<u8>>) {
+    let command = Command::decode_bytes(frame.clone()).unwrap();
+    let body = command.body().map(|body| body.to_vec());
+    (command, body)
+}
+
+fn encode(c: &mut Criterion) {
+    let command = command();
+    let body = command.body().unwrap().to_vec();
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Bytes(BODY_SIZE as u64));
+    group.bench_function("vec", |b| b.iter(|| black_box(encode_vec(&command, &body))));
+    // Header and body as separate buffers for a vectored write.
+    group.bench_function("vectored", |b| {
+        b.iter(|| {
+            let mut header = BytesMut::new();
+            command.encode_header(&mut header);
+            black_box((header, command.body().cloned()))
+        })
+    });
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let frame = Bytes::from(command().encode());
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(BODY_SIZE as u64));
+    group.bench_function("vec", |b| b.iter(|| black_box(decode_vec(&frame))));
+    group.bench_function("shared", |b| {
+        b.iter(|| black_box(Command::decode_bytes(frame.clone()).unwrap()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,129 @@
+//! Signing of requests to brokers and name servers with ACL enabled, `AclClientRPCHook`
+//! in Java.
+
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::{common::command::Command, hook::RpcHook};
+
+pub const ACCESS_KEY: &str = "AccessKey";
+pub const SIGNATURE: &str = "Signature";
+pub const SECURITY_TOKEN: &str = "SecurityToken";
+
+/// The keys an ACL account signs with, `SessionCredentials` in Java.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionCredentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub security_token: Option<String>,
+}
+
+impl SessionCredentials {
+    pub fn new(access_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+        Self {
+            access_key: access_key.into(),
+            secret_key: secret_key.into(),
+            security_token: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for SessionCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionCredentials")
+            .field("access_key", &self.access_key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Adds `AccessKey`, the optional `SecurityToken` and a `Signature` to every request:
+/// the Base64 HMAC-SHA1 of the values of all ext fields, sorted by key, followed by the
+/// body.
+#[derive(Debug, Clone)]
+pub struct AclClientRpcHook {
+    credentials: SessionCredentials,
+}
+
+impl AclClientRpcHook {
+    pub fn new(credentials: SessionCredentials) -> Self {
+        Self { credentials }
+    }
+}
+
+impl RpcHook for AclClientRpcHook {
+    fn before_request(&self, _remote_addr: &str, request: &mut Command) {
+        request.add_property(ACCESS_KEY, &self.credentials.access_key);
+        if let Some(token) = &self.credentials.security_token {
+            request.add_property(SECURITY_TOKEN, token);
+        }
+        let signature = signature(request, &self.credentials.secret_key);
+        request.add_property(SIGNATURE, signature);
+    }
+}
+
+/// Signs the ext fields and body of `request`, `AclUtils.calSignature` in Java.
+pub fn signature(request: &Command, secret_key: &str) -> String {
+    let sorted: BTreeMap<_, _> = request
+        .properties()
+        .iter()
+        .filter(|(key, _)| *key != SIGNATURE)
+        .collect();
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret_key.as_bytes()).unwrap();
+    for value in sorted.values() {
+        mac.update(value.as_bytes());
+    }
+    if let Some(body) = request.body() {
+        mac.update(body);
+    }
+    STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> Command {
+        let mut request = Command::new(0);
+        request.add_property("topic", "TopicTest");
+        request.add_property("queueId", "1");
+        request
+    }
+
+    #[test]
+    fn test_sign() {
+        let hook = AclClientRpcHook::new(SessionCredentials::new("RocketMQ", "12345678"));
+        let mut request = request();
+        request.set_body(b"Hello".to_vec());
+        hook.before_request("127.0.0.1:10911", &mut request);
+        assert_eq!("RocketMQ", request.get_property(ACCESS_KEY).unwrap());
+        assert_eq!(None, request.get_property(SECURITY_TOKEN));
+        // HMAC-SHA1 of "RocketMQ" + "1" + "TopicTest" + "Hello".
+        assert_eq!(
+            "ZFvi/WuIPR3bqsk5pNgHavciF+Q=",
+            request.get_property(SIGNATURE).unwrap()
+        );
+        // Signing again leaves out the previous signature.
+        assert_eq!(
+            request.get_property(SIGNATURE).unwrap(),
+            &signature(&request, "12345678")
+        );
+    }
+
+    #[test]
+    fn test_sign_with_security_token() {
+        let hook = AclClientRpcHook::new(SessionCredentials {
+            security_token: Some("token".to_string()),
+            ..SessionCredentials::new("RocketMQ", "12345678")
+        });
+        let mut request = request();
+        hook.before_request("127.0.0.1:10911", &mut request);
+        assert_eq!("token", request.get_property(SECURITY_TOKEN).unwrap());
+        assert_eq!(
+            "zN83u20VwmGXA0kyhVEiW8LyibU=",
+            request.get_property(SIGNATURE).unwrap()
+        );
+    }
+}
@@ -0,0 +1,607 @@
+//! Typed requests to brokers.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::{
+    client::RemotingClient,
+    common::{
+        code::{RequestCode, ResponseCode},
+        command::Command,
+        compression::MessageCompressor,
+        header::broker::{
+            EndTransactionRequestHeader, GetMaxOffsetRequestHeader, GetMaxOffsetResponseHeader,
+            GetMinOffsetRequestHeader, GetMinOffsetResponseHeader, PullMessageRequestHeader,
+            PullMessageResponseHeader, QueryConsumerOffsetRequestHeader,
+            QueryConsumerOffsetResponseHeader, SearchOffsetRequestHeader,
+            SearchOffsetResponseHeader, SendMessageRequestHeader, SendMessageRequestHeaderV2,
+            SendMessageResponseHeader, UpdateConsumerOffsetRequestHeader, ViewMessageRequestHeader,
+        },
+        message::{self, MessageExt},
+        message_id::MessageId,
+    },
+    util::Error,
+};
+
+/// How far a sent message got, `SendStatus` in Java. Every status but
+/// [`SendStatus::SendOk`] means the message was stored on the master only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+    SendOk,
+    FlushDiskTimeout,
+    FlushSlaveTimeout,
+    SlaveNotAvailable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendResult {
+    pub status: SendStatus,
+    /// Id the broker derived from its address and the commit log offset.
+    pub offset_msg_id: String,
+    pub queue_id: i32,
+    pub queue_offset: i64,
+    pub transaction_id: Option<String>,
+}
+
+/// Outcome of a pull, `PullStatus` in Java.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullStatus {
+    Found,
+    NoNewMsg,
+    NoMatchedMsg,
+    OffsetIllegal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullResult {
+    pub status: PullStatus,
+    pub next_begin_offset: i64,
+    pub min_offset: i64,
+    pub max_offset: i64,
+    pub suggest_which_broker_id: i64,
+    /// The stored messages, back to back, when [`PullStatus::Found`].
+    pub body: Option<Bytes>,
+}
+
+impl PullResult {
+    /// Decodes the pulled messages. The bodies share the memory of [`PullResult::body`].
+    pub fn messages(&self) -> Result<Vec<MessageExt>, Error> {
+        match &self.body {
+            Some(body) => message::decode_all(body.clone()),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Sends typed requests to brokers through a [`RemotingClient`], like `MQClientAPIImpl`
+/// in Java.
+///
+/// Responses with a code the request can't succeed with fail with
+/// [`Error::RemoteError`].
+#[derive(Clone)]
+pub struct BrokerClient {
+    client: RemotingClient,
+    compressor: Option<MessageCompressor>,
+}
+
+impl BrokerClient {
+    /// Creates a client that compresses bodies like a Java producer does by default.
+    pub fn new(client: RemotingClient) -> Self {
+        Self::with_compressor(client, Some(MessageCompressor::default()))
+    }
+
+    /// Creates a client that compresses bodies sent with [`BrokerClient::send_message`]
+    /// with `compressor`, or sends them as they are if it is `None`.
+    pub fn with_compressor(client: RemotingClient, compressor: Option<MessageCompressor>) -> Self {
+        Self { client, compressor }
+    }
+
+    pub fn remoting_client(&self) -> &RemotingClient {
+        &self.client
+    }
+
+    /// Sends a message with `SEND_MESSAGE_V2`, which carries the header under compact
+    /// names. The body is compressed if it is over the threshold of the compressor, which
+    /// `sys_flag` of the header then tells the broker.
+    pub async fn send_message(
+        &self,
+        addr: &str,
+        header: &SendMessageRequestHeader,
+        body: impl Into<Bytes>,
+    ) -> Result<SendResult, Error> {
+        let mut body = body.into();
+        let mut header = header.clone();
+        if let Some(compressor) = &self.compressor {
+            body = compressor.compress(&mut header, body)?;
+        }
+        self.send(addr, RequestCode::SendMessageV2, &header, body)
+            .await
+    }
+
+    /// Sends messages encoded by [`message::encode_batch`] with `SEND_BATCH_MESSAGE`.
+    /// `batch` of the header is set.
+    pub async fn send_batch(
+        &self,
+        addr: &str,
+        header: &SendMessageRequestHeader,
+        body: impl Into<Bytes>,
+    ) -> Result<SendResult, Error> {
+        let header = SendMessageRequestHeader {
+            batch: Some(true),
+            ..header.clone()
+        };
+        self.send(addr, RequestCode::SendBatchMessage, &header, body.into())
+            .await
+    }
+
+    async fn send(
+        &self,
+        addr: &str,
+        code: RequestCode,
+        header: &SendMessageRequestHeader,
+        body: Bytes,
+    ) -> Result<SendResult, Error> {
+        let mut request = Command::new(code).with_header(&SendMessageRequestHeaderV2::from(header));
+        request.set_body(body);
+        let response = self.client.invoke(addr, request).await?;
+        let (status, response) = match response.response_code() {
+            Some(ResponseCode::FlushDiskTimeout) => (SendStatus::FlushDiskTimeout, response),
+            Some(ResponseCode::FlushSlaveTimeout) => (SendStatus::FlushSlaveTimeout, response),
+            Some(ResponseCode::SlaveNotAvailable) => (SendStatus::SlaveNotAvailable, response),
+            _ => (SendStatus::SendOk, response.ensure_success()?),
+        };
+        let response_header: SendMessageResponseHeader = response.decode_header()?;
+        Ok(SendResult {
+            status,
+            offset_msg_id: response_header.msg_id,
+            queue_id: response_header.queue_id,
+            queue_offset: response_header.queue_offset,
+            transaction_id: response_header.transaction_id,
+        })
+    }
+
+    /// Pulls messages from a queue. The broker may hold the request for
+    /// `suspend_timeout_millis` of the header, so `timeout` should be longer than that.
+    pub async fn pull_message(
+        &self,
+        addr: &str,
+        header: &PullMessageRequestHeader,
+        timeout: Duration,
+    ) -> Result<PullResult, Error> {
+        let request = Command::new(RequestCode::PullMessage).with_header(header);
+        let response = self
+            .client
+            .invoke_async(addr, request, timeout)
+            .await?
+            .await?;
+        let (status, response) = match response.response_code() {
+            Some(ResponseCode::PullNotFound) => (PullStatus::NoNewMsg, response),
+            Some(ResponseCode::PullRetryImmediately) => (PullStatus::NoMatchedMsg, response),
+            Some(ResponseCode::PullOffsetMoved) => (PullStatus::OffsetIllegal, response),
+            _ => (PullStatus::Found, response.ensure_success()?),
+        };
+        let response_header: PullMessageResponseHeader = response.decode_header()?;
+        Ok(PullResult {
+            status,
+            next_begin_offset: response_header.next_begin_offset,
+            min_offset: response_header.min_offset,
+            max_offset: response_header.max_offset,
+            suggest_which_broker_id: response_header.suggest_which_broker_id,
+            body: response.body().cloned(),
+        })
+    }
+
+    /// The offset a consumer group committed for a queue, or `None` if it never did.
+    pub async fn query_consumer_offset(
+        &self,
+        addr: &str,
+        header: &QueryConsumerOffsetRequestHeader,
+    ) -> Result<Option<i64>, Error> {
+        let request = Command::new(RequestCode::QueryConsumerOffset).with_header(header);
+        let response = self.client.invoke(addr, request).await?;
+        if response.response_code() == Some(ResponseCode::QueryNotFound) {
+            return Ok(None);
+        }
+        let response_header: QueryConsumerOffsetResponseHeader =
+            response.ensure_success()?.decode_header()?;
+        Ok(Some(response_header.offset))
+    }
+
+    pub async fn update_consumer_offset(
+        &self,
+        addr: &str,
+        header: &UpdateConsumerOffsetRequestHeader,
+    ) -> Result<(), Error> {
+        let request = Command::new(RequestCode::UpdateConsumerOffset).with_header(header);
+        self.client.invoke(addr, request).await?.ensure_success()?;
+        Ok(())
+    }
+
+    /// The offset after the last message of a queue.
+    pub async fn get_max_offset(
+        &self,
+        addr: &str,
+        header: &GetMaxOffsetRequestHeader,
+    ) -> Result<i64, Error> {
+        let request = Command::new(RequestCode::GetMaxOffset).with_header(header);
+        let response = self.client.invoke(addr, request).await?.ensure_success()?;
+        let response_header: GetMaxOffsetResponseHeader = response.decode_header()?;
+        Ok(response_header.offset)
+    }
+
+    /// The offset of the oldest message still stored in a queue.
+    pub async fn get_min_offset(
+        &self,
+        addr: &str,
+        header: &GetMinOffsetRequestHeader,
+    ) -> Result<i64, Error> {
+        let request = Command::new(RequestCode::GetMinOffset).with_header(header);
+        let response = self.client.invoke(addr, request).await?.ensure_success()?;
+        let response_header: GetMinOffsetResponseHeader = response.decode_header()?;
+        Ok(response_header.offset)
+    }
+
+    /// The offset of the first message of a queue stored at or after `timestamp`.
+    pub async fn search_offset_by_timestamp(
+        &self,
+        addr: &str,
+        header: &SearchOffsetRequestHeader,
+    ) -> Result<i64, Error> {
+        let request = Command::new(RequestCode::SearchOffsetByTimestamp).with_header(header);
+        let response = self.client.invoke(addr, request).await?.ensure_success()?;
+        let response_header: SearchOffsetResponseHeader = response.decode_header()?;
+        Ok(response_header.offset)
+    }
+
+    /// Looks a message up by its offset message id, on the broker the id names.
+    pub async fn view_message(&self, offset_msg_id: &str) -> Result<MessageExt, Error> {
+        let id = MessageId::decode(offset_msg_id)?;
+        let header = ViewMessageRequestHeader {
+            offset: id.commit_log_offset,
+        };
+        let request = Command::new(RequestCode::ViewMessageById).with_header(&header);
+        let response = self
+            .client
+            .invoke(&id.store_host.to_string(), request)
+            .await?
+            .ensure_success()?;
+        let mut body = response
+            .body()
+            .cloned()
+            .ok_or_else(|| Error::DecodeCommandError("missing body".to_string()))?;
+        message::decode(&mut body)
+    }
+
+    /// Commits or rolls back a half message. Sent oneway, like in Java, since the broker
+    /// checks back on transactions it never hears about.
+    pub async fn end_transaction(
+        &self,
+        addr: &str,
+        header: &EndTransactionRequestHeader,
+        remark: Option<String>,
+    ) -> Result<(), Error> {
+        let mut request = Command::new(RequestCode::EndTransaction).with_header(header);
+        if let Some(remark) = remark {
+            request.set_remark(remark);
+        }
+        self.client.invoke_oneway(addr, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        client::RemotingClientConfig,
+        common::{
+            compression::{CompressionType, DEFAULT_MAX_BODY_SIZE},
+            message::sys_flag,
+        },
+        processor::RequestProcessor,
+        server::{RemotingServer, ServerHandle},
+    };
+
+    /// Stores every message in queue 1 and reports flushing to disk as slow for the
+    /// second one, or stores it at offset 2 if it arrived compressed. Tells end
+    /// transaction requests apart through `transactions`.
+    struct FakeBroker {
+        transactions: mpsc::UnboundedSender<EndTransactionRequestHeader>,
+    }
+
+    #[async_trait]
+    impl RequestProcessor for FakeBroker {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            request: Command,
+        ) -> Result<Option<Command>, Error> {
+            let mut response = Command::new_response(ResponseCode::Success);
+            match RequestCode::try_from(request.code())? {
+                code @ (RequestCode::SendMessageV2 | RequestCode::SendBatchMessage) => {
+                    let header: SendMessageRequestHeaderV2 = request.decode_header()?;
+                    assert_eq!(
+                        code == RequestCode::SendBatchMessage,
+                        header.m == Some(true)
+                    );
+                    assert_eq!("TopicTest", header.b);
+                    let body = request.body().unwrap();
+                    let queue_offset = if header.f & sys_flag::COMPRESSED != 0 {
+                        let body = CompressionType::from_sys_flag(header.f)?
+                            .decompress(body, DEFAULT_MAX_BODY_SIZE)?;
+                        assert!(body.iter().all(|b| *b == b'a'));
+                        2
+                    } else {
+                        match &body[..] {
+                            b"first" => 0,
+                            _ => {
+                                response = Command::new_response(ResponseCode::FlushDiskTimeout);
+                                1
+                            }
+                        }
+                    };
+                    response = response.with_header(&SendMessageResponseHeader {
+                        msg_id: "7F00000100002A9F0000000000000000".to_string(),
+                        queue_id: 1,
+                        queue_offset,
+                        ..Default::default()
+                    });
+                }
+                RequestCode::PullMessage => {
+                    let header: PullMessageRequestHeader = request.decode_header()?;
+                    if header.queue_offset > 0 {
+                        response = Command::new_response(ResponseCode::PullNotFound);
+                    } else {
+                        let stored = MessageExt {
+                            message: message::Message::new("TopicTest", b"stored".to_vec()),
+                            ..Default::default()
+                        };
+                        response.set_body(message::encode(&stored)?);
+                    }
+                    response = response.with_header(&PullMessageResponseHeader {
+                        next_begin_offset: 1,
+                        max_offset: 1,
+                        ..Default::default()
+                    });
+                }
+                RequestCode::QueryConsumerOffset => {
+                    let header: QueryConsumerOffsetRequestHeader = request.decode_header()?;
+                    if header.consumer_group != "group" {
+                        response = Command::new_response(ResponseCode::QueryNotFound);
+                    } else {
+                        response =
+                            response.with_header(&QueryConsumerOffsetResponseHeader { offset: 42 });
+                    }
+                }
+                RequestCode::UpdateConsumerOffset => {}
+                RequestCode::GetMaxOffset => {
+                    response = response.with_header(&GetMaxOffsetResponseHeader { offset: 100 });
+                }
+                RequestCode::GetMinOffset => {
+                    response = Command::new_response(ResponseCode::SystemError);
+                    response.set_remark("store not ready");
+                }
+                RequestCode::SearchOffsetByTimestamp => {
+                    let header: SearchOffsetRequestHeader = request.decode_header()?;
+                    response = response.with_header(&SearchOffsetResponseHeader {
+                        offset: header.timestamp / 1000,
+                    });
+                }
+                RequestCode::ViewMessageById => {
+                    let header: ViewMessageRequestHeader = request.decode_header()?;
+                    let stored = MessageExt {
+                        message: message::Message::new("TopicTest", b"viewed".to_vec()),
+                        commit_log_offset: header.offset,
+                        ..Default::default()
+                    };
+                    response.set_body(message::encode(&stored)?);
+                }
+                RequestCode::EndTransaction => {
+                    assert!(request.is_oneway());
+                    self.transactions.send(request.decode_header()?).unwrap();
+                    return Ok(None);
+                }
+                _ => {
+                    return Ok(Some(Command::new_response(
+                        ResponseCode::RequestCodeNotSupported,
+                    )))
+                }
+            }
+            Ok(Some(response))
+        }
+    }
+
+    async fn start() -> (
+        ServerHandle,
+        BrokerClient,
+        mpsc::UnboundedReceiver<EndTransactionRequestHeader>,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_default_processor(Arc::new(FakeBroker { transactions: tx }), None);
+        let server = server.start().unwrap();
+        let client = BrokerClient::new(RemotingClient::new(RemotingClientConfig::default()));
+        (server, client, rx)
+    }
+
+    fn send_header() -> SendMessageRequestHeader {
+        SendMessageRequestHeader {
+            producer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            default_topic: "TBW102".to_string(),
+            default_topic_queue_nums: 4,
+            queue_id: 1,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (server, client, _) = start().await;
+        let addr = server.local_addr().to_string();
+
+        let result = client
+            .send_message(&addr, &send_header(), b"first".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(SendStatus::SendOk, result.status);
+        assert_eq!(1, result.queue_id);
+        assert_eq!(0, result.queue_offset);
+
+        let result = client
+            .send_batch(&addr, &send_header(), b"second".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(SendStatus::FlushDiskTimeout, result.status);
+        assert_eq!(1, result.queue_offset);
+    }
+
+    #[tokio::test]
+    async fn test_send_compressed() {
+        let (server, client, _) = start().await;
+        let addr = server.local_addr().to_string();
+        let body = "a".repeat(8 * 1024);
+
+        let result = client
+            .send_message(&addr, &send_header(), body.clone())
+            .await
+            .unwrap();
+        assert_eq!(2, result.queue_offset);
+        let result = client
+            .send_message(&addr, &send_header(), b"a".repeat(1024))
+            .await
+            .unwrap();
+        assert_eq!(1, result.queue_offset);
+
+        let compressor = MessageCompressor {
+            compression_type: CompressionType::Lz4,
+            threshold: 16,
+            ..Default::default()
+        };
+        let client = BrokerClient::with_compressor(client.client.clone(), Some(compressor));
+        let result = client
+            .send_message(&addr, &send_header(), b"a".repeat(1024))
+            .await
+            .unwrap();
+        assert_eq!(2, result.queue_offset);
+        // Batches go out as they are.
+        let result = client
+            .send_batch(&addr, &send_header(), body.clone())
+            .await
+            .unwrap();
+        assert_eq!(1, result.queue_offset);
+
+        let client = BrokerClient::with_compressor(client.client.clone(), None);
+        let result = client
+            .send_message(&addr, &send_header(), body)
+            .await
+            .unwrap();
+        assert_eq!(1, result.queue_offset);
+    }
+
+    #[tokio::test]
+    async fn test_view_message() {
+        let (server, client, _) = start().await;
+        let id = MessageId::new(server.local_addr(), 4096).encode();
+
+        let message = client.view_message(&id).await.unwrap();
+        assert_eq!(4096, message.commit_log_offset);
+        assert_eq!(&b"viewed"[..], &message.message.body[..]);
+        assert!(client.view_message("not an id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pull_message() {
+        let (server, client, _) = start().await;
+        let addr = server.local_addr().to_string();
+        let mut header = PullMessageRequestHeader {
+            consumer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            max_msg_nums: 32,
+            ..Default::default()
+        };
+
+        let result = client
+            .pull_message(&addr, &header, Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert_eq!(PullStatus::Found, result.status);
+        assert_eq!(1, result.next_begin_offset);
+        let messages = result.messages().unwrap();
+        assert_eq!(1, messages.len());
+        assert_eq!(&b"stored"[..], &messages[0].message.body[..]);
+
+        header.queue_offset = 1;
+        let result = client
+            .pull_message(&addr, &header, Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert_eq!(PullStatus::NoNewMsg, result.status);
+        assert_eq!(None, result.body);
+    }
+
+    #[tokio::test]
+    async fn test_offsets() {
+        let (server, client, mut transactions) = start().await;
+        let addr = server.local_addr().to_string();
+
+        let mut header = QueryConsumerOffsetRequestHeader {
+            consumer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(42),
+            client.query_consumer_offset(&addr, &header).await.unwrap()
+        );
+        header.consumer_group = "unknown".to_string();
+        assert_eq!(
+            None,
+            client.query_consumer_offset(&addr, &header).await.unwrap()
+        );
+
+        client
+            .update_consumer_offset(&addr, &UpdateConsumerOffsetRequestHeader::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            100,
+            client
+                .get_max_offset(&addr, &GetMaxOffsetRequestHeader::default())
+                .await
+                .unwrap()
+        );
+        let error = client
+            .get_min_offset(&addr, &GetMinOffsetRequestHeader::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::RemoteError { code: 1, remark } if remark == "store not ready"
+        ));
+        let header = SearchOffsetRequestHeader {
+            timestamp: 7000,
+            ..Default::default()
+        };
+        assert_eq!(
+            7,
+            client
+                .search_offset_by_timestamp(&addr, &header)
+                .await
+                .unwrap()
+        );
+
+        let header = EndTransactionRequestHeader {
+            producer_group: "group".to_string(),
+            msg_id: "7F00000100002A9F0000000000000000".to_string(),
+            ..Default::default()
+        };
+        client.end_transaction(&addr, &header, None).await.unwrap();
+        assert_eq!(header, transactions.recv().await.unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    common::{command::SerializeType, frame::DEFAULT_MAX_FRAME_SIZE},
+    hook::{RpcHook, RpcHooks},
+};
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsClientConfig;
+
+use super::Backoff;
+
+/// Default of each limit on requests in flight, like `clientAsyncSemaphoreValue` and
+/// `clientOnewaySemaphoreValue` in Java.
+pub const DEFAULT_MAX_REQUESTS: usize = 65535;
+
+/// Settings of a [`Channel`](super::Channel), created with [`ChannelConfig::builder`].
+#[derive(Debug, Clone)]
+pub struct ChannelConfig {
+    pub(crate) backoff: Backoff,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) request_timeout: Duration,
+    pub(crate) serialize_type: SerializeType,
+    pub(crate) send_buffer_size: Option<u32>,
+    pub(crate) recv_buffer_size: Option<u32>,
+    pub(crate) keepalive: bool,
+    pub(crate) max_frame_size: usize,
+    pub(crate) rpc_hooks: RpcHooks,
+    pub(crate) max_sync_requests: usize,
+    pub(crate) max_async_requests: usize,
+    pub(crate) max_oneway_requests: usize,
+    pub(crate) wait_for_permit: bool,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsClientConfig>,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            connect_timeout: Duration::from_secs(3),
+            request_timeout: Duration::from_secs(10),
+            serialize_type: SerializeType::Json,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            keepalive: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            rpc_hooks: RpcHooks::default(),
+            max_sync_requests: DEFAULT_MAX_REQUESTS,
+            max_async_requests: DEFAULT_MAX_REQUESTS,
+            max_oneway_requests: DEFAULT_MAX_REQUESTS,
+            wait_for_permit: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ChannelConfig {
+    pub fn builder() -> ChannelConfigBuilder {
+        ChannelConfigBuilder::default()
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ChannelConfigBuilder {
+    config: ChannelConfig,
+}
+
+impl ChannelConfigBuilder {
+    /// Delays between reconnect attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.config.backoff = backoff;
+        self
+    }
+
+    /// How long a single connect attempt may take before it counts as failed.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    /// How long [`Channel::request`](super::Channel::request) waits for a response.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    pub fn serialize_type(mut self, serialize_type: SerializeType) -> Self {
+        self.config.serialize_type = serialize_type;
+        self
+    }
+
+    /// `SO_SNDBUF` of the socket, left to the OS if not set.
+    pub fn send_buffer_size(mut self, size: u32) -> Self {
+        self.config.send_buffer_size = Some(size);
+        self
+    }
+
+    /// `SO_RCVBUF` of the socket, left to the OS if not set.
+    pub fn recv_buffer_size(mut self, size: u32) -> Self {
+        self.config.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Enables `SO_KEEPALIVE` on the socket.
+    pub fn keepalive(mut self, keepalive: bool) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    /// Largest frame read or written. A peer sending a larger one is disconnected,
+    /// commands larger than this fail with [`Error::FrameTooLarge`](crate::util::Error::FrameTooLarge).
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.config.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// How many requests sent with [`Channel::request`](super::Channel::request) may
+    /// wait for their response at once.
+    pub fn max_sync_requests(mut self, max: usize) -> Self {
+        self.config.max_sync_requests = max;
+        self
+    }
+
+    /// How many requests sent with
+    /// [`Channel::request_async`](super::Channel::request_async) may wait for their
+    /// response at once.
+    pub fn max_async_requests(mut self, max: usize) -> Self {
+        self.config.max_async_requests = max;
+        self
+    }
+
+    /// How many oneway requests may wait to be written at once.
+    pub fn max_oneway_requests(mut self, max: usize) -> Self {
+        self.config.max_oneway_requests = max;
+        self
+    }
+
+    /// Whether requests over a limit wait for a slot, up to their timeout, instead of
+    /// failing with [`Error::TooManyRequests`](crate::util::Error::TooManyRequests) right
+    /// away. Waiting requests also wait for room in the write queue instead of failing
+    /// with [`Error::ChannelFull`](crate::util::Error::ChannelFull).
+    pub fn wait_for_permit(mut self, wait: bool) -> Self {
+        self.config.wait_for_permit = wait;
+        self
+    }
+
+    /// Adds a hook that sees every request before it is written and every response
+    /// after it is read. Hooks run in the order they were added.
+    pub fn rpc_hook(mut self, hook: Arc<dyn RpcHook>) -> Self {
+        self.config.rpc_hooks.push(hook);
+        self
+    }
+
+    /// Connects with TLS instead of plain TCP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsClientConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    pub fn build(self) -> ChannelConfig {
+        self.config
+    }
+}
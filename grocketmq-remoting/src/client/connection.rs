@@ -0,0 +1,300 @@
+use std::{future, io, net::SocketAddr, sync::Arc, time::Instant};
+
+use futures_util::StreamExt;
+use tokio::{
+    net::{lookup_host, TcpSocket},
+    select,
+    sync::{mpsc, oneshot, watch},
+    time::{sleep, sleep_until, timeout},
+};
+
+use metrics::counter;
+use tracing::{debug, warn};
+
+use crate::{
+    common::{
+        command::Command,
+        frame::{self, BoxStream},
+    },
+    metrics::{RECEIVED_BYTES, RECONNECTS, SENT_BYTES},
+    processor::{self, ProcessorTable},
+    util::Error,
+};
+
+use super::{response_table::ResponseTable, AbandonedRequest, ChannelConfig, ConnectionState};
+
+/// A command queued for the connection.
+pub(super) struct Outgoing {
+    pub command: Command,
+    /// Notified once a oneway command was written. Requests learn about write
+    /// failures through the response table instead.
+    pub written_tx: Option<oneshot::Sender<Result<(), Error>>>,
+}
+
+/// Asks the connection to close once its requests are answered, or at `deadline`.
+pub(super) struct Close {
+    pub deadline: Instant,
+    /// Receives the requests that were still queued or waiting for a response.
+    pub report_tx: oneshot::Sender<Vec<AbandonedRequest>>,
+}
+
+/// Why a connection stopped serving.
+enum Disconnect {
+    Broken,
+    Shutdown,
+}
+
+/// The I/O task behind a [`Channel`](super::Channel).
+pub(super) struct Connection {
+    pub addr: String,
+    pub config: ChannelConfig,
+    pub rx: mpsc::Receiver<Outgoing>,
+    pub response_table: Arc<ResponseTable>,
+    pub processors: Arc<ProcessorTable>,
+    pub state_tx: watch::Sender<ConnectionState>,
+    /// Ends once every channel handle is gone, which closes the connection right away.
+    pub close_rx: mpsc::UnboundedReceiver<Close>,
+    /// Set once the connection drains, until when it waits for responses.
+    pub drain_deadline: Option<Instant>,
+    pub close_reports: Vec<oneshot::Sender<Vec<AbandonedRequest>>>,
+}
+
+impl Connection {
+    /// Keeps a connection to `addr` alive until the channel is closed, then fails every
+    /// request that is still queued or waiting for a response and reports them to the
+    /// callers of [`Channel::close`](super::Channel::close).
+    pub async fn run(mut self) {
+        let backoff = self.config.backoff;
+        let mut delay = backoff.initial;
+        let mut connected_before = false;
+        loop {
+            self.state_tx.send_replace(ConnectionState::Connecting);
+            let stream = select! {
+                result = Connection::new_stream(&self.addr, &self.config) => result,
+                close = self.close_rx.recv() => {
+                    // Nothing can be answered without a connection, so don't wait.
+                    self.close_reports.extend(close.map(|close| close.report_tx));
+                    break;
+                }
+            };
+            let (stream, peer_addr) = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(remote_addr = %self.addr, error = %e, retry_in = ?delay, "connect failed");
+                    select! {
+                        _ = sleep(delay) => {}
+                        close = self.close_rx.recv() => {
+                            self.close_reports.extend(close.map(|close| close.report_tx));
+                            break;
+                        }
+                    }
+                    delay = (delay * 2).min(backoff.max);
+                    continue;
+                }
+            };
+
+            delay = backoff.initial;
+            if connected_before {
+                counter!(RECONNECTS, "addr" => self.addr.clone()).increment(1);
+            }
+            connected_before = true;
+            debug!(remote_addr = %self.addr, %peer_addr, "connected");
+            self.state_tx.send_replace(ConnectionState::Active);
+            let disconnect = self.serve(stream, peer_addr).await;
+            if let Disconnect::Shutdown = disconnect {
+                break;
+            }
+            warn!(remote_addr = %self.addr, %peer_addr, "connection broken");
+            if self.drain_deadline.is_some() {
+                break;
+            }
+            self.response_table.fail_all();
+        }
+        self.state_tx.send_replace(ConnectionState::Closed);
+        self.rx.close();
+        let mut abandoned = Vec::new();
+        while let Ok(outgoing) = self.rx.try_recv() {
+            // Queued requests are reported from the response table.
+            if outgoing.written_tx.is_some() {
+                abandoned.push(AbandonedRequest {
+                    opaque: outgoing.command.opaque(),
+                    code: outgoing.command.code(),
+                });
+            }
+        }
+        abandoned.extend(self.response_table.fail_all());
+        if !abandoned.is_empty() {
+            debug!(remote_addr = %self.addr, abandoned = abandoned.len(), "closed with requests in flight");
+        }
+        self.close_rx.close();
+        while let Ok(close) = self.close_rx.try_recv() {
+            self.close_reports.push(close.report_tx);
+        }
+        for report_tx in self.close_reports.drain(..) {
+            let _ = report_tx.send(abandoned.clone());
+        }
+    }
+
+    /// Stops taking new commands, so the connection closes once the queued ones are
+    /// written and answered.
+    fn drain(&mut self, close: Close) {
+        if self.drain_deadline.is_none() {
+            self.rx.close();
+        }
+        self.drain_deadline = Some(match self.drain_deadline {
+            Some(deadline) => deadline.min(close.deadline),
+            None => close.deadline,
+        });
+        self.close_reports.push(close.report_tx);
+    }
+
+    /// Writes requests, dispatches responses and answers requests from the peer until
+    /// the stream breaks or the channel is closed. Closing shuts the stream down cleanly
+    /// once it drained.
+    async fn serve(&mut self, stream: BoxStream, peer_addr: SocketAddr) -> Disconnect {
+        let (mut reader, mut writer) = frame::split(stream, self.config.max_frame_size);
+        // Responses to the peer's requests, only valid on this stream.
+        let (reply_tx, mut reply_rx) = mpsc::unbounded_channel();
+        // Whether every queued command was taken after the queue closed.
+        let mut queue_done = false;
+
+        let disconnect = loop {
+            if self.drain_deadline.is_some() && queue_done && self.response_table.len() == 0 {
+                break Disconnect::Shutdown;
+            }
+            let drain_deadline = self.drain_deadline;
+            select! {
+                outgoing = self.rx.recv(), if !queue_done => {
+                    let Some(Outgoing { command, written_tx }) = outgoing else {
+                        queue_done = true;
+                        continue;
+                    };
+                    let opaque = command.opaque();
+                    if written_tx.is_none() && !self.response_table.contains(opaque) {
+                        // Timed out or abandoned while queued.
+                        continue;
+                    }
+                    let result = writer.send(command).await;
+                    let broken = frame::is_broken(&result);
+                    let result = result.map(|written| self.record_sent(written));
+                    match (written_tx, result) {
+                        (Some(written_tx), result) => {
+                            let _ = written_tx.send(result);
+                        }
+                        (None, Err(e)) => self.response_table.fail(opaque, e),
+                        (None, Ok(())) => {}
+                    }
+                    if broken {
+                        break Disconnect::Broken;
+                    }
+                }
+                Some(reply) = reply_rx.recv() => {
+                    match writer.send(reply).await {
+                        Ok(written) => self.record_sent(written),
+                        result if frame::is_broken(&result) => break Disconnect::Broken,
+                        Err(_) => {}
+                    }
+                }
+                command = reader.next() => {
+                    counter!(RECEIVED_BYTES, "addr" => self.addr.clone())
+                        .increment(reader.decoder_mut().take_bytes_read() as u64);
+                    match command {
+                        Some(Ok(mut command)) if command.is_response() => {
+                            self.config.rpc_hooks.after_response(&self.addr, &mut command);
+                            self.response_table.complete(command);
+                        }
+                        Some(Ok(command)) => self.process(peer_addr, command, reply_tx.clone()),
+                        // Closed, or sent a frame that can't be decoded.
+                        _ => break Disconnect::Broken,
+                    }
+                }
+                close = self.close_rx.recv() => {
+                    match close {
+                        Some(close) => self.drain(close),
+                        // Every handle is gone, so nobody waits for responses anymore.
+                        None => break Disconnect::Shutdown,
+                    }
+                }
+                _ = async {
+                    match drain_deadline {
+                        Some(deadline) => sleep_until(deadline.into()).await,
+                        None => future::pending().await,
+                    }
+                } => break Disconnect::Shutdown,
+            }
+        };
+        if let Disconnect::Shutdown = disconnect {
+            let _ = writer.shutdown().await;
+        }
+        disconnect
+    }
+
+    fn record_sent(&self, written: usize) {
+        counter!(SENT_BYTES, "addr" => self.addr.clone()).increment(written as u64);
+    }
+
+    /// Answers a request from the peer in the background, so a slow processor doesn't
+    /// hold up the responses this side is waiting for.
+    fn process(
+        &self,
+        remote_addr: SocketAddr,
+        request: Command,
+        reply_tx: mpsc::UnboundedSender<Command>,
+    ) {
+        let processor = self.processors.get(request.code());
+        tokio::spawn(async move {
+            if let Some(response) = processor::process(processor, remote_addr, request).await {
+                let _ = reply_tx.send(response);
+            }
+        });
+    }
+
+    /// Resolves the address and connects to the first of its IPs that accepts.
+    async fn new_stream(
+        addr: &str,
+        config: &ChannelConfig,
+    ) -> Result<(BoxStream, SocketAddr), Error> {
+        let mut last_error = None;
+        for socket_addr in lookup_host(addr).await? {
+            let connected = timeout(
+                config.connect_timeout,
+                Connection::connect(addr, socket_addr, config),
+            )
+            .await;
+            match connected {
+                Ok(Ok(stream)) => return Ok((stream, socket_addr)),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(_) => last_error = Some(io::Error::from(io::ErrorKind::TimedOut).into()),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::InvalidAddress(addr.to_string())))
+    }
+
+    /// Connects to one IP of `addr`, including the TLS handshake if configured.
+    async fn connect(
+        addr: &str,
+        socket_addr: SocketAddr,
+        config: &ChannelConfig,
+    ) -> Result<BoxStream, Error> {
+        let socket = match socket_addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket.set_nodelay(true)?;
+        socket.set_keepalive(config.keepalive)?;
+        if let Some(size) = config.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = config.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        let stream = socket.connect(socket_addr).await?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &config.tls {
+            return Ok(Box::new(tls.connect(addr, stream).await?));
+        }
+        #[cfg(not(feature = "tls"))]
+        let _ = addr;
+        Ok(Box::new(stream))
+    }
+}
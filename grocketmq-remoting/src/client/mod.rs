@@ -0,0 +1,960 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, watch, OwnedSemaphorePermit, Semaphore,
+    },
+    time::timeout_at,
+};
+use tracing::{debug, debug_span};
+
+pub use self::{
+    config::{ChannelConfig, ChannelConfigBuilder},
+    remoting_client::{RemotingClient, RemotingClientConfig},
+    response_table::ResponseFuture,
+};
+
+use crate::{
+    common::command::{Command, SerializeType},
+    hook::RpcHooks,
+    processor::{Executor, ProcessorTable, Registration, RequestProcessor},
+    util::Error,
+};
+
+use self::{
+    connection::{Close, Connection, Outgoing},
+    response_table::ResponseTable,
+};
+
+mod config;
+mod connection;
+mod remoting_client;
+mod response_table;
+
+/// A connection to one peer that requests are multiplexed over, reconnecting whenever
+/// the stream breaks.
+///
+/// Clones are cheap and share the connection, so tasks can send over one connection
+/// per peer like in Java; the timeout and header format are set per clone. Dropping the
+/// last clone closes the connection right away and fails the requests in flight;
+/// [`Channel::close`] closes it for every clone and lets the requests finish first.
+#[derive(Clone)]
+pub struct Channel {
+    addr: Arc<str>,
+    command_sender: mpsc::Sender<Outgoing>,
+    response_table: Arc<ResponseTable>,
+    processors: Arc<ProcessorTable>,
+    timeout: Duration,
+    serialize_type: SerializeType,
+    rpc_hooks: RpcHooks,
+    permits: Permits,
+    state_rx: watch::Receiver<ConnectionState>,
+    close_tx: mpsc::UnboundedSender<Close>,
+}
+
+/// State of the connection behind a [`Channel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connecting for the first time, or reconnecting after the stream broke.
+    Connecting,
+    /// The stream is established and requests are written to it.
+    Active,
+    /// The channel was shut down and won't reconnect.
+    Closed,
+}
+
+/// A request that was still queued or waiting for its response when its channel closed.
+/// It failed with [`Error::ConnectionClosed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbandonedRequest {
+    pub opaque: i32,
+    pub code: i32,
+}
+
+/// Delays between reconnect attempts, doubling from `initial` up to `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+/**
+ * A channel sends and receives Command messages.
+ */
+impl Channel {
+    pub async fn new(addr: &str) -> Result<Self, Error> {
+        Self::with_backoff(addr, Backoff::default()).await
+    }
+
+    /// Creates a channel that reconnects with the given backoff whenever the stream breaks.
+    pub async fn with_backoff(addr: &str, backoff: Backoff) -> Result<Self, Error> {
+        Self::with_config(addr, ChannelConfig::builder().backoff(backoff).build()).await
+    }
+
+    /// Creates a channel to `addr`, either `host:port` or `ip:port` with IPv6 addresses
+    /// in brackets. Host names are resolved again on every reconnect.
+    pub async fn with_config(addr: &str, config: ChannelConfig) -> Result<Self, Error> {
+        validate_addr(addr)?;
+        let timeout = config.request_timeout;
+        let serialize_type = config.serialize_type;
+        let rpc_hooks = config.rpc_hooks.clone();
+        let permits = Permits::new(&config);
+        let (tx, rx) = mpsc::channel(1024);
+        let (close_tx, close_rx) = mpsc::unbounded_channel();
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Connecting);
+        let response_table = ResponseTable::new(addr);
+        let processors = Arc::new(ProcessorTable::default());
+
+        let connection = Connection {
+            addr: addr.to_string(),
+            config,
+            rx,
+            response_table: response_table.clone(),
+            processors: processors.clone(),
+            state_tx,
+            close_rx,
+            drain_deadline: None,
+            close_reports: Vec::new(),
+        };
+        tokio::spawn(connection.run());
+        Ok(Self {
+            addr: addr.into(),
+            command_sender: tx,
+            response_table,
+            processors,
+            timeout,
+            serialize_type,
+            rpc_hooks,
+            permits,
+            state_rx,
+            close_tx,
+        })
+    }
+
+    /// Current state of the underlying connection.
+    pub fn state(&self) -> ConnectionState {
+        *self.state_rx.borrow()
+    }
+
+    /// Number of requests waiting for a response.
+    pub fn in_flight(&self) -> usize {
+        self.response_table.len()
+    }
+
+    /// Registers the processor answering requests with `code` that the peer sends over
+    /// this channel, e.g. `CHECK_TRANSACTION_STATE` from a broker. Requests without a
+    /// processor are answered with `RequestCodeNotSupported`.
+    pub fn register_processor(&self, code: impl Into<i32>, processor: Arc<dyn RequestProcessor>) {
+        self.processors.register(
+            code.into(),
+            Registration {
+                processor,
+                executor: Arc::new(Executor::unbounded()),
+            },
+        );
+    }
+
+    /// Whether `other` is a clone of this channel, sharing its connection.
+    pub fn same_channel(&self, other: &Channel) -> bool {
+        self.close_tx.same_channel(&other.close_tx)
+    }
+
+    /// Subscribes to connection state changes.
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.state_rx.clone()
+    }
+
+    /// Sets how long requests wait for their response.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the header format used for commands written on this channel.
+    /// Responses are decoded in whichever format the peer chose.
+    pub fn set_serialize_type(&mut self, serialize_type: SerializeType) {
+        self.serialize_type = serialize_type;
+    }
+
+    /// Sends a request and waits for its response, up to the channel timeout.
+    pub async fn request(&self, cmd: Command) -> Result<Command, Error> {
+        self.send_request(cmd, self.timeout, &self.permits.sync)
+            .await?
+            .await
+    }
+
+    /// Sends a request without waiting for its response. The returned future resolves
+    /// to the response, or fails once `timeout` elapsed; it can also hand the result
+    /// to a callback with [`ResponseFuture::on_complete`].
+    pub async fn request_async(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<ResponseFuture, Error> {
+        self.send_request(cmd, timeout, &self.permits.r#async).await
+    }
+
+    /// Queues a request once it got a permit of `semaphore`, which is released when the
+    /// request ends. Time spent waiting for the permit counts towards `timeout`.
+    async fn send_request(
+        &self,
+        mut cmd: Command,
+        timeout: Duration,
+        semaphore: &Arc<Semaphore>,
+    ) -> Result<ResponseFuture, Error> {
+        cmd.set_serialize_type(self.serialize_type);
+        self.rpc_hooks.before_request(&self.addr, &mut cmd);
+        let opaque = cmd.opaque();
+        let span = debug_span!("request", code = cmd.code(), opaque, remote_addr = %self.addr);
+        let deadline = Instant::now() + timeout;
+        let permit = match self.permits.acquire(semaphore, opaque, deadline).await {
+            Ok(permit) => permit,
+            Err(e) => {
+                debug!(parent: &span, error = %e, "no permit");
+                return Err(e);
+            }
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let response_rx = self
+            .response_table
+            .register(opaque, cmd.code(), timeout, permit);
+        let outgoing = Outgoing {
+            command: cmd,
+            written_tx: None,
+        };
+        if let Err(e) = self.enqueue(outgoing, deadline).await {
+            self.response_table.fail(opaque, Error::ConnectionClosed);
+            debug!(parent: &span, error = %e, "request not queued");
+            return Err(e);
+        }
+        Ok(ResponseFuture::new(
+            opaque,
+            timeout,
+            response_rx,
+            self.response_table.clone(),
+            span,
+        ))
+    }
+
+    /// Sends a request the peer won't answer, e.g. a heartbeat, and waits until it was
+    /// written to the connection.
+    pub async fn send_oneway(&self, mut cmd: Command) -> Result<(), Error> {
+        cmd.set_serialize_type(self.serialize_type);
+        cmd.mark_oneway();
+        self.rpc_hooks.before_request(&self.addr, &mut cmd);
+        let opaque = cmd.opaque();
+        let span = debug_span!("oneway", code = cmd.code(), opaque, remote_addr = %self.addr);
+        let started = Instant::now();
+        let deadline = started + self.timeout;
+        let result = async {
+            let _permit = self
+                .permits
+                .acquire(&self.permits.oneway, opaque, deadline)
+                .await?;
+            let (written_tx, written_rx) = oneshot::channel();
+            let outgoing = Outgoing {
+                command: cmd,
+                written_tx: Some(written_tx),
+            };
+            self.enqueue(outgoing, deadline).await?;
+            match timeout_at(deadline.into(), written_rx).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(_)) => Err(Error::ConnectionClosed),
+                Err(_) => Err(Error::Timeout {
+                    opaque,
+                    elapsed: started.elapsed(),
+                }),
+            }
+        }
+        .await;
+        if let Err(e) = &result {
+            debug!(parent: &span, error = %e, "oneway request not written");
+        }
+        result
+    }
+
+    /// Stops taking requests, then closes the connection once every request in flight got
+    /// its response, or once `drain_timeout` elapsed. Returns the requests that were
+    /// still waiting by then, which fail with [`Error::ConnectionClosed`].
+    pub async fn close(&self, drain_timeout: Duration) -> Vec<AbandonedRequest> {
+        let (report_tx, report_rx) = oneshot::channel();
+        let close = Close {
+            deadline: Instant::now() + drain_timeout,
+            report_tx,
+        };
+        if self.close_tx.send(close).is_err() {
+            // Already closed.
+            return Vec::new();
+        }
+        report_rx.await.unwrap_or_default()
+    }
+
+    /// Hands a command to the connection. Fails right away if the queue is full, unless
+    /// the channel waits for permits, in which case it waits for room until `deadline`.
+    async fn enqueue(&self, outgoing: Outgoing, deadline: Instant) -> Result<(), Error> {
+        if !self.permits.wait {
+            return self.command_sender.try_send(outgoing).map_err(|e| match e {
+                TrySendError::Full(_) => Error::ChannelFull,
+                TrySendError::Closed(_) => Error::ConnectionClosed,
+            });
+        }
+        let opaque = outgoing.command.opaque();
+        let started = Instant::now();
+        match timeout_at(deadline.into(), self.command_sender.reserve()).await {
+            Ok(Ok(slot)) => {
+                slot.send(outgoing);
+                Ok(())
+            }
+            Ok(Err(_)) => Err(Error::ConnectionClosed),
+            Err(_) => Err(Error::Timeout {
+                opaque,
+                elapsed: started.elapsed(),
+            }),
+        }
+    }
+}
+
+/// Limits on the requests of a channel in flight, one per kind of request.
+#[derive(Clone)]
+struct Permits {
+    sync: Arc<Semaphore>,
+    r#async: Arc<Semaphore>,
+    oneway: Arc<Semaphore>,
+    wait: bool,
+}
+
+impl Permits {
+    fn new(config: &ChannelConfig) -> Self {
+        Self {
+            sync: Arc::new(Semaphore::new(config.max_sync_requests)),
+            r#async: Arc::new(Semaphore::new(config.max_async_requests)),
+            oneway: Arc::new(Semaphore::new(config.max_oneway_requests)),
+            wait: config.wait_for_permit,
+        }
+    }
+
+    /// Takes a permit of `semaphore`, waiting for one until `deadline` if configured to.
+    async fn acquire(
+        &self,
+        semaphore: &Arc<Semaphore>,
+        opaque: i32,
+        deadline: Instant,
+    ) -> Result<OwnedSemaphorePermit, Error> {
+        if !self.wait {
+            return semaphore
+                .clone()
+                .try_acquire_owned()
+                .map_err(|_| Error::TooManyRequests);
+        }
+        let started = Instant::now();
+        match timeout_at(deadline.into(), semaphore.clone().acquire_owned()).await {
+            Ok(permit) => Ok(permit.expect("semaphore is never closed")),
+            Err(_) => Err(Error::Timeout {
+                opaque,
+                elapsed: started.elapsed(),
+            }),
+        }
+    }
+}
+
+/// Checks that `addr` has a host and a port, without resolving it.
+fn validate_addr(addr: &str) -> Result<(), Error> {
+    if addr.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+    let invalid = || Error::InvalidAddress(addr.to_string());
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    // A bare IPv6 address without brackets, or a host without a name.
+    if host.is_empty() || host.contains(':') || port.parse::<u16>().is_err() {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("state", &self.state())
+            .field("in_flight", &self.in_flight())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use async_trait::async_trait;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::{
+        acl::{self, AclClientRpcHook, SessionCredentials},
+        common::{
+            code::{RequestCode, ResponseCode},
+            header::client::NotifyConsumerIdsChangedRequestHeader,
+        },
+        hook::RpcHook,
+    };
+
+    async fn read_command(stream: &mut TcpStream) -> Option<Command> {
+        let mut length = [0; 4];
+        stream.read_exact(&mut length).await.ok()?;
+        let mut frame = length.to_vec();
+        frame.resize(4 + u32::from_be_bytes(length) as usize, 0);
+        stream.read_exact(&mut frame[4..]).await.ok()?;
+        Command::decode(&frame).ok()
+    }
+
+    async fn respond(stream: &mut TcpStream, request: &Command) {
+        let mut response = Command::new_response(ResponseCode::Success);
+        response.set_opaque(request.opaque());
+        stream.write_all(&response.encode()).await.unwrap();
+    }
+
+    async fn wait_for(channel: &Channel, state: ConnectionState) {
+        let mut state_rx = channel.subscribe();
+        timeout(Duration::from_secs(5), state_rx.wait_for(|s| *s == state))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_host_name_and_ipv6() {
+        for bind_addr in ["127.0.0.1:0", "[::1]:0"] {
+            let listener = TcpListener::bind(bind_addr).await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let addr = if listener.local_addr().unwrap().is_ipv6() {
+                format!("[::1]:{}", port)
+            } else {
+                format!("localhost:{}", port)
+            };
+            let config = ChannelConfig::builder()
+                .keepalive(true)
+                .send_buffer_size(64 * 1024)
+                .recv_buffer_size(64 * 1024)
+                .request_timeout(Duration::from_secs(3))
+                .build();
+            let channel = Channel::with_config(&addr, config).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            wait_for(&channel, ConnectionState::Active).await;
+
+            let responder = tokio::spawn(async move {
+                let request = read_command(&mut stream).await.unwrap();
+                respond(&mut stream, &request).await;
+            });
+            let response = channel
+                .request(Command::new(RequestCode::HeartBeat))
+                .await
+                .unwrap();
+            assert_eq!(Some(ResponseCode::Success), response.response_code());
+            responder.await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate_addr() {
+        assert!(validate_addr("127.0.0.1:9876").is_ok());
+        assert!(validate_addr("[::1]:9876").is_ok());
+        assert!(validate_addr("broker-a.internal:10911").is_ok());
+        assert!(validate_addr("broker-a.internal").is_err());
+        assert!(validate_addr("::1:9876").is_err());
+        assert!(validate_addr(":9876").is_err());
+        assert!(validate_addr("localhost:99999").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_disconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::with_backoff(
+            &addr,
+            Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+            },
+        )
+        .await
+        .unwrap();
+
+        // The first connection answers one request, then drops while another is in flight.
+        let (mut stream, _) = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+        let server = tokio::spawn(async move {
+            let request = read_command(&mut stream).await.unwrap();
+            respond(&mut stream, &request).await;
+            read_command(&mut stream).await.unwrap();
+        });
+        assert!(channel
+            .request(Command::new(0))
+            .await
+            .unwrap()
+            .is_response());
+        assert!(matches!(
+            channel.request(Command::new(0)).await,
+            Err(Error::ConnectionClosed)
+        ));
+        server.await.unwrap();
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+        tokio::spawn(async move {
+            while let Some(request) = read_command(&mut stream).await {
+                respond(&mut stream, &request).await;
+            }
+        });
+        assert!(channel
+            .request(Command::new(0))
+            .await
+            .unwrap()
+            .is_response());
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut channel = Channel::new(&addr).await.unwrap();
+        channel.set_timeout(Duration::from_millis(50));
+        let (_stream, _) = listener.accept().await.unwrap();
+
+        let command = Command::new(0);
+        let opaque = command.opaque();
+        match channel.request(command).await {
+            Err(Error::Timeout {
+                opaque: timed_out,
+                elapsed,
+            }) => {
+                assert_eq!(opaque, timed_out);
+                assert!(elapsed >= Duration::from_millis(50));
+            }
+            _ => panic!("request should time out"),
+        }
+        assert_eq!(0, channel.in_flight());
+    }
+
+    #[tokio::test]
+    async fn test_oneway_and_async() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        channel.send_oneway(Command::new(0)).await.unwrap();
+        let oneway = read_command(&mut stream).await.unwrap();
+        assert!(oneway.is_oneway());
+        assert_eq!(0, channel.in_flight());
+
+        let response = channel
+            .request_async(Command::new(0), Duration::from_secs(3))
+            .await
+            .unwrap();
+        let (callback_tx, callback_rx) = oneshot::channel();
+        response.on_complete(move |response| {
+            let _ = callback_tx.send(response);
+        });
+        let request = read_command(&mut stream).await.unwrap();
+        assert!(!request.is_oneway());
+        respond(&mut stream, &request).await;
+        let response = callback_rx.await.unwrap().unwrap();
+        assert_eq!(request.opaque(), response.opaque());
+
+        let response = channel
+            .request_async(Command::new(0), Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert!(matches!(response.await, Err(Error::Timeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_close_drains_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+
+        let response = channel
+            .request_async(Command::new(0), Duration::from_secs(3))
+            .await
+            .unwrap();
+        let request = read_command(&mut stream).await.unwrap();
+        let close = tokio::spawn({
+            let channel = channel.clone();
+            async move { channel.close(Duration::from_secs(3)).await }
+        });
+        // Closing waits for the response, but takes no new requests.
+        while channel.command_sender.try_reserve().is_ok() {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            channel.request(Command::new(0)).await,
+            Err(Error::ConnectionClosed)
+        ));
+        assert!(!close.is_finished());
+
+        respond(&mut stream, &request).await;
+        assert!(response.await.is_ok());
+        assert_eq!(Vec::<AbandonedRequest>::new(), close.await.unwrap());
+        // The stream was shut down cleanly.
+        assert!(read_command(&mut stream).await.is_none());
+        assert_eq!(ConnectionState::Closed, channel.state());
+    }
+
+    #[tokio::test]
+    async fn test_close_abandons_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+
+        let command = Command::new(RequestCode::PullMessage);
+        let opaque = command.opaque();
+        let response = channel
+            .request_async(command, Duration::from_secs(3))
+            .await
+            .unwrap();
+        read_command(&mut stream).await.unwrap();
+
+        let abandoned = channel.close(Duration::from_millis(50)).await;
+        assert_eq!(
+            vec![AbandonedRequest {
+                opaque,
+                code: RequestCode::PullMessage.code(),
+            }],
+            abandoned
+        );
+        assert!(matches!(response.await, Err(Error::ConnectionClosed)));
+        assert_eq!(0, channel.in_flight());
+        assert!(channel.close(Duration::from_secs(1)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_too_many_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = ChannelConfig::builder().max_async_requests(1).build();
+        let channel = Channel::with_config(&addr, config).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let first = channel
+            .request_async(Command::new(0), Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert!(matches!(
+            channel
+                .request_async(Command::new(0), Duration::from_secs(3))
+                .await,
+            Err(Error::TooManyRequests)
+        ));
+        let request = read_command(&mut stream).await.unwrap();
+        respond(&mut stream, &request).await;
+        first.await.unwrap();
+
+        // The answered request gave its permit back.
+        let second = channel
+            .request_async(Command::new(0), Duration::from_secs(3))
+            .await
+            .unwrap();
+        let request = read_command(&mut stream).await.unwrap();
+        respond(&mut stream, &request).await;
+        second.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_permit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = ChannelConfig::builder()
+            .max_sync_requests(1)
+            .wait_for_permit(true)
+            .request_timeout(Duration::from_secs(3))
+            .build();
+        let channel = Channel::with_config(&addr, config).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let first = tokio::spawn({
+            let channel = channel.clone();
+            async move { channel.request(Command::new(0)).await }
+        });
+        let request = read_command(&mut stream).await.unwrap();
+        let second = tokio::spawn({
+            let channel = channel.clone();
+            async move { channel.request(Command::new(0)).await }
+        });
+        // The second request waits for the first one's permit before it is written.
+        assert!(
+            timeout(Duration::from_millis(50), read_command(&mut stream))
+                .await
+                .is_err()
+        );
+        respond(&mut stream, &request).await;
+        first.await.unwrap().unwrap();
+        let request = read_command(&mut stream).await.unwrap();
+        respond(&mut stream, &request).await;
+        second.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_permit_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = ChannelConfig::builder()
+            .max_async_requests(1)
+            .wait_for_permit(true)
+            .build();
+        let channel = Channel::with_config(&addr, config).await.unwrap();
+        let (_stream, _) = listener.accept().await.unwrap();
+
+        let _first = channel
+            .request_async(Command::new(0), Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert!(matches!(
+            channel
+                .request_async(Command::new(0), Duration::from_millis(50))
+                .await,
+            Err(Error::Timeout { .. })
+        ));
+        assert_eq!(1, channel.in_flight());
+    }
+
+    /// Counts responses and marks them, so the test can tell the hook saw them.
+    struct ResponseCounter(std::sync::atomic::AtomicUsize);
+
+    impl RpcHook for ResponseCounter {
+        fn before_request(&self, _remote_addr: &str, _request: &mut Command) {}
+
+        fn after_response(&self, _remote_addr: &str, response: &mut Command) {
+            let count = self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            response.add_property("seen", count.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_hooks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let credentials = SessionCredentials::new("RocketMQ", "12345678");
+        let config = ChannelConfig::builder()
+            .rpc_hook(Arc::new(AclClientRpcHook::new(credentials)))
+            .rpc_hook(Arc::new(ResponseCounter(Default::default())))
+            .build();
+        let channel = Channel::with_config(&addr, config).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut request = Command::new(RequestCode::GetAllTopicListFromNameServer);
+        request.add_property("topic", "TopicTest");
+        let response = tokio::spawn(async move { channel.request(request).await });
+        let request = read_command(&mut stream).await.unwrap();
+        assert_eq!("RocketMQ", request.get_property(acl::ACCESS_KEY).unwrap());
+        assert_eq!(
+            &acl::signature(&request, "12345678"),
+            request.get_property(acl::SIGNATURE).unwrap()
+        );
+        respond(&mut stream, &request).await;
+        let response = response.await.unwrap().unwrap();
+        assert_eq!("1", response.get_property("seen").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        use crate::metrics::{RECEIVED_BYTES, RECONNECTS, REQUESTS, REQUEST_DURATION, SENT_BYTES};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        recorder.install().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        // Codes no other test uses, as the recorder is global.
+        let answered = tokio::spawn({
+            let channel = channel.clone();
+            async move { channel.request(Command::new(9001)).await }
+        });
+        let request = read_command(&mut stream).await.unwrap();
+        respond(&mut stream, &request).await;
+        answered.await.unwrap().unwrap();
+        let unanswered = channel
+            .request_async(Command::new(9002), Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert!(unanswered.await.is_err());
+
+        drop(stream);
+        wait_for(&channel, ConnectionState::Connecting).await;
+        let _stream = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let value = |name: &str, labels: &[(&str, &str)]| {
+            snapshot
+                .iter()
+                .find(|(key, _)| {
+                    let key = key.key();
+                    key.name() == name
+                        && labels.iter().all(|(label, value)| {
+                            key.labels()
+                                .any(|l| l.key() == *label && l.value() == *value)
+                        })
+                })
+                .map(|(_, (_, _, value))| value)
+        };
+        let counter = |name: &str, labels: &[(&str, &str)]| match value(name, labels) {
+            Some(DebugValue::Counter(value)) => *value,
+            _ => 0,
+        };
+        assert_eq!(
+            1,
+            counter(REQUESTS, &[("code", "9001"), ("outcome", "response")])
+        );
+        assert_eq!(
+            1,
+            counter(REQUESTS, &[("code", "9002"), ("outcome", "timeout")])
+        );
+        assert!(matches!(
+            value(REQUEST_DURATION, &[("code", "9001")]),
+            Some(DebugValue::Histogram(values)) if values.len() == 1
+        ));
+        assert!(counter(SENT_BYTES, &[("addr", &addr)]) > 0);
+        assert!(counter(RECEIVED_BYTES, &[("addr", &addr)]) > 0);
+        assert_eq!(1, counter(RECONNECTS, &[("addr", &addr)]));
+    }
+
+    struct NotifyProcessor {
+        groups_tx: mpsc::UnboundedSender<String>,
+    }
+
+    #[async_trait]
+    impl RequestProcessor for NotifyProcessor {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            request: Command,
+        ) -> Result<Option<Command>, Error> {
+            let header: NotifyConsumerIdsChangedRequestHeader = request.decode_header()?;
+            let _ = self.groups_tx.send(header.consumer_group);
+            Ok(Some(Command::new_response(ResponseCode::Success)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_server_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let (groups_tx, mut groups_rx) = mpsc::unbounded_channel();
+        channel.register_processor(
+            RequestCode::NotifyConsumerIdsChanged,
+            Arc::new(NotifyProcessor { groups_tx }),
+        );
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut request = Command::new(RequestCode::NotifyConsumerIdsChanged).with_header(
+            &NotifyConsumerIdsChangedRequestHeader {
+                consumer_group: "group".to_string(),
+            },
+        );
+        request.set_opaque(42);
+        stream.write_all(&request.encode()).await.unwrap();
+        let response = read_command(&mut stream).await.unwrap();
+        assert_eq!(42, response.opaque());
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+        assert_eq!("group", groups_rx.recv().await.unwrap());
+
+        let mut request = Command::new(RequestCode::GetConsumerRunningInfo);
+        request.set_opaque(43);
+        stream.write_all(&request.encode()).await.unwrap();
+        let response = read_command(&mut stream).await.unwrap();
+        assert_eq!(43, response.opaque());
+        assert_eq!(
+            Some(ResponseCode::RequestCodeNotSupported),
+            response.response_code()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_on_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let mut state_rx = channel.subscribe();
+        let _ = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+
+        drop(channel);
+        timeout(
+            Duration::from_secs(5),
+            state_rx.wait_for(|s| *s == ConnectionState::Closed),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let channel = Channel::new(&addr).await.unwrap();
+        let mut state_rx = channel.subscribe();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        wait_for(&channel, ConnectionState::Active).await;
+        tokio::spawn(async move {
+            while let Some(request) = read_command(&mut stream).await {
+                respond(&mut stream, &request).await;
+            }
+        });
+
+        let mut clone = channel.clone();
+        assert!(clone.same_channel(&channel));
+        assert!(!clone.same_channel(&Channel::new(&addr).await.unwrap()));
+        clone.set_timeout(Duration::from_millis(500));
+        let requests = (0..4).map(|_| {
+            let channel = channel.clone();
+            tokio::spawn(async move { channel.request(Command::new(0)).await })
+        });
+        for request in requests {
+            assert!(request.await.unwrap().unwrap().is_response());
+        }
+
+        // The connection outlives the first handle and closes with the last one.
+        drop(channel);
+        clone.request(Command::new(0)).await.unwrap();
+        assert_eq!(ConnectionState::Active, *state_rx.borrow());
+        drop(clone);
+        timeout(
+            Duration::from_secs(5),
+            state_rx.wait_for(|s| *s == ConnectionState::Closed),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::time::timeout;
+
+use crate::{common::command::Command, util::Error};
+
+use super::{Channel, ChannelConfig, ConnectionState, ResponseFuture};
+
+/// How often idle and closed channels are evicted.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct RemotingClientConfig {
+    /// How long to wait for a new channel to connect before giving up on its address.
+    pub connect_timeout: Duration,
+    /// Timeout of requests sent with [`RemotingClient::invoke`].
+    pub request_timeout: Duration,
+    /// Channels without requests for this long are closed.
+    pub idle_timeout: Duration,
+    /// Settings of every channel the client creates.
+    pub channel: ChannelConfig,
+}
+
+impl Default for RemotingClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(3),
+            request_timeout: Duration::from_secs(3),
+            idle_timeout: Duration::from_secs(120),
+            channel: ChannelConfig::default(),
+        }
+    }
+}
+
+struct CachedChannel {
+    channel: Channel,
+    last_used: Instant,
+}
+
+struct Inner {
+    config: RemotingClientConfig,
+    channels: Mutex<HashMap<String, CachedChannel>>,
+    name_servers: RwLock<Vec<String>>,
+    /// Index of the name server requests go to until it fails.
+    name_server_index: AtomicUsize,
+}
+
+/// Sends commands to any number of addresses, keeping one [`Channel`] per address.
+///
+/// Channels are created on first use and closed once idle. Requests meant for the name
+/// servers stick to one of the configured addresses and fail over to the next one when
+/// it can't be reached.
+#[derive(Clone)]
+pub struct RemotingClient {
+    inner: Arc<Inner>,
+}
+
+impl RemotingClient {
+    pub fn new(config: RemotingClientConfig) -> Self {
+        let inner = Arc::new(Inner {
+            config,
+            channels: Mutex::new(HashMap::new()),
+            name_servers: RwLock::new(Vec::new()),
+            name_server_index: AtomicUsize::new(0),
+        });
+        tokio::spawn(RemotingClient::scan_periodically(Arc::downgrade(&inner)));
+        Self { inner }
+    }
+
+    pub fn update_name_server_addresses(&self, addresses: Vec<String>) {
+        *self.inner.name_servers.write().unwrap() = addresses;
+    }
+
+    pub fn name_server_addresses(&self) -> Vec<String> {
+        self.inner.name_servers.read().unwrap().clone()
+    }
+
+    /// Sends a request to `addr` and waits for its response.
+    pub async fn invoke(&self, addr: &str, cmd: Command) -> Result<Command, Error> {
+        let mut channel = self.channel(addr).await?;
+        channel.set_timeout(self.inner.config.request_timeout);
+        channel.request(cmd).await
+    }
+
+    /// Sends a request to `addr` and returns a future of its response.
+    pub async fn invoke_async(
+        &self,
+        addr: &str,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<ResponseFuture, Error> {
+        let channel = self.channel(addr).await?;
+        channel.request_async(cmd, timeout).await
+    }
+
+    /// Sends a request to `addr` that won't be answered.
+    pub async fn invoke_oneway(&self, addr: &str, cmd: Command) -> Result<(), Error> {
+        let channel = self.channel(addr).await?;
+        channel.send_oneway(cmd).await
+    }
+
+    /// Sends a request to the name servers. The command is built by `make_command`, so
+    /// it can be sent again to the next name server if the current one is unreachable.
+    pub async fn invoke_name_server<F>(&self, make_command: F) -> Result<Command, Error>
+    where
+        F: Fn() -> Command,
+    {
+        let addresses = self.name_server_addresses();
+        if addresses.is_empty() {
+            return Err(Error::InvalidAddress("no name server address".to_string()));
+        }
+        let start = self.inner.name_server_index.load(Ordering::Relaxed);
+        let mut last_error = None;
+        for i in 0..addresses.len() {
+            let index = (start + i) % addresses.len();
+            let addr = &addresses[index];
+            match self.invoke(addr, make_command()).await {
+                Err(e) if Self::is_unreachable(&e) => {
+                    self.close_channel(addr);
+                    last_error = Some(e);
+                }
+                result => {
+                    self.inner.name_server_index.store(index, Ordering::Relaxed);
+                    return result;
+                }
+            }
+        }
+        self.inner
+            .name_server_index
+            .store((start + 1) % addresses.len(), Ordering::Relaxed);
+        Err(last_error.unwrap_or(Error::StreamNotReady))
+    }
+
+    /// Whether an error means the peer can't be reached, rather than it refusing the request.
+    fn is_unreachable(error: &Error) -> bool {
+        matches!(
+            error,
+            Error::StreamNotReady
+                | Error::ConnectionClosed
+                | Error::ChannelFull
+                | Error::Timeout { .. }
+                | Error::IoError(_)
+        )
+    }
+
+    /// Returns the cached channel for `addr`, connecting a new one if needed.
+    pub async fn channel(&self, addr: &str) -> Result<Channel, Error> {
+        if let Some(channel) = self.cached_channel(addr) {
+            return Ok(channel);
+        }
+
+        let channel = Channel::with_config(addr, self.inner.config.channel.clone()).await?;
+        let channel = {
+            let mut channels = self.inner.channels.lock().unwrap();
+            let cached = channels
+                .entry(addr.to_string())
+                .or_insert_with(|| CachedChannel {
+                    channel,
+                    last_used: Instant::now(),
+                });
+            cached.channel.clone()
+        };
+
+        let mut state_rx = channel.subscribe();
+        let connected = timeout(
+            self.inner.config.connect_timeout,
+            state_rx.wait_for(|state| *state == ConnectionState::Active),
+        )
+        .await;
+        match connected {
+            Ok(Ok(_)) => Ok(channel),
+            _ => {
+                self.close_channel(addr);
+                Err(Error::StreamNotReady)
+            }
+        }
+    }
+
+    fn cached_channel(&self, addr: &str) -> Option<Channel> {
+        let mut channels = self.inner.channels.lock().unwrap();
+        let cached = channels.get_mut(addr)?;
+        if cached.channel.state() == ConnectionState::Closed {
+            channels.remove(addr);
+            return None;
+        }
+        cached.last_used = Instant::now();
+        Some(cached.channel.clone())
+    }
+
+    /// Forgets the channel for `addr`. It closes once every request using it is done.
+    pub fn close_channel(&self, addr: &str) {
+        self.inner.channels.lock().unwrap().remove(addr);
+    }
+
+    async fn scan_periodically(inner: Weak<Inner>) {
+        let mut interval = tokio::time::interval(SCAN_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            let idle_timeout = inner.config.idle_timeout;
+            inner.channels.lock().unwrap().retain(|_, cached| {
+                cached.channel.state() != ConnectionState::Closed
+                    && (cached.last_used.elapsed() < idle_timeout || cached.channel.in_flight() > 0)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use async_trait::async_trait;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        common::code::{RequestCode, ResponseCode},
+        processor::RequestProcessor,
+        server::{RemotingServer, ServerHandle},
+    };
+
+    /// Answers with the address of the server in the remark.
+    struct NameProcessor {
+        name: String,
+    }
+
+    #[async_trait]
+    impl RequestProcessor for NameProcessor {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            _request: Command,
+        ) -> Result<Option<Command>, Error> {
+            let mut response = Command::new_response(ResponseCode::Success);
+            response.set_remark(self.name.clone());
+            Ok(Some(response))
+        }
+    }
+
+    async fn start_server() -> ServerHandle {
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        let name = server.local_addr().unwrap().to_string();
+        server.register_default_processor(Arc::new(NameProcessor { name }), None);
+        server.start().unwrap()
+    }
+
+    /// An address nothing listens on.
+    async fn unused_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn config() -> RemotingClientConfig {
+        RemotingClientConfig {
+            connect_timeout: Duration::from_millis(200),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invoke_caches_channels() {
+        let server = start_server().await;
+        let addr = server.local_addr().to_string();
+        let client = RemotingClient::new(config());
+
+        let response = client
+            .invoke(&addr, Command::new(RequestCode::HeartBeat))
+            .await
+            .unwrap();
+        assert_eq!(Some(addr.as_str()), response.remark());
+        let channel = client.channel(&addr).await.unwrap();
+        client
+            .invoke(&addr, Command::new(RequestCode::HeartBeat))
+            .await
+            .unwrap();
+        assert!(channel.same_channel(&client.channel(&addr).await.unwrap()));
+        assert_eq!(1, client.inner.channels.lock().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_invoke_takes_no_async_permit() {
+        let server = start_server().await;
+        let addr = server.local_addr().to_string();
+        let client = RemotingClient::new(RemotingClientConfig {
+            channel: ChannelConfig::builder().max_async_requests(0).build(),
+            ..config()
+        });
+
+        client
+            .invoke(&addr, Command::new(RequestCode::HeartBeat))
+            .await
+            .unwrap();
+        let result = client
+            .invoke_async(
+                &addr,
+                Command::new(RequestCode::HeartBeat),
+                Duration::from_secs(1),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::TooManyRequests)));
+    }
+
+    #[tokio::test]
+    async fn test_name_server_failover() {
+        let server = start_server().await;
+        let addr = server.local_addr().to_string();
+        let client = RemotingClient::new(config());
+        client.update_name_server_addresses(vec![unused_addr().await, addr.clone()]);
+
+        let response = client
+            .invoke_name_server(|| Command::new(RequestCode::GetRouteInfoByTopic))
+            .await
+            .unwrap();
+        assert_eq!(Some(addr.as_str()), response.remark());
+        // Sticks to the reachable name server.
+        assert_eq!(1, client.inner.name_server_index.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_evict_idle_channels() {
+        let server = start_server().await;
+        let addr = server.local_addr().to_string();
+        let client = RemotingClient::new(RemotingClientConfig {
+            idle_timeout: Duration::from_millis(10),
+            ..config()
+        });
+
+        client
+            .invoke(&addr, Command::new(RequestCode::HeartBeat))
+            .await
+            .unwrap();
+        tokio::time::sleep(SCAN_INTERVAL * 2).await;
+        assert!(client.inner.channels.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{oneshot, OwnedSemaphorePermit},
+    time::{sleep, Sleep},
+};
+use tracing::{debug, Span};
+
+use crate::{
+    common::command::Command,
+    metrics::{self, OUTCOME_RESPONSE},
+    util::Error,
+};
+
+use super::AbandonedRequest;
+
+/// How often expired entries are swept, like `scanResponseTable` in Java.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) type ResponseReceiver = oneshot::Receiver<Result<Command, Error>>;
+
+struct ResponseEntry {
+    response_tx: oneshot::Sender<Result<Command, Error>>,
+    code: i32,
+    started: Instant,
+    timeout: Duration,
+    /// Held until the request ends, to limit the requests in flight.
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ResponseEntry {
+    fn send(self, result: Result<Command, Error>) {
+        let outcome = match &result {
+            Ok(_) => OUTCOME_RESPONSE,
+            Err(e) => metrics::outcome(e),
+        };
+        metrics::record_request(self.code, outcome, self.started.elapsed());
+        let _ = self.response_tx.send(result);
+    }
+}
+
+/// Requests waiting for a response, keyed by opaque.
+///
+/// Every entry is completed exactly once: by its response, by a failure of the
+/// connection, or with [`Error::Timeout`] once its deadline passed.
+pub(crate) struct ResponseTable {
+    /// Address of the channel, to label metrics with.
+    addr: String,
+    entries: Mutex<HashMap<i32, ResponseEntry>>,
+}
+
+impl ResponseTable {
+    /// Creates a table whose expired entries are swept in the background for as
+    /// long as the table is alive.
+    pub fn new(addr: &str) -> Arc<Self> {
+        let table = Arc::new(Self {
+            addr: addr.to_string(),
+            entries: Mutex::default(),
+        });
+        tokio::spawn(Self::sweep_periodically(Arc::downgrade(&table)));
+        table
+    }
+
+    async fn sweep_periodically(table: Weak<Self>) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match table.upgrade() {
+                Some(table) => table.sweep(),
+                None => break,
+            }
+        }
+    }
+
+    pub fn register(
+        &self,
+        opaque: i32,
+        code: i32,
+        timeout: Duration,
+        permit: OwnedSemaphorePermit,
+    ) -> ResponseReceiver {
+        let (response_tx, response_rx) = oneshot::channel();
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            opaque,
+            ResponseEntry {
+                response_tx,
+                code,
+                started: Instant::now(),
+                timeout,
+                _permit: permit,
+            },
+        );
+        metrics::record_in_flight(&self.addr, entries.len());
+        response_rx
+    }
+
+    fn remove(&self, opaque: i32) -> Option<ResponseEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.remove(&opaque)?;
+        metrics::record_in_flight(&self.addr, entries.len());
+        Some(entry)
+    }
+
+    pub fn contains(&self, opaque: i32) -> bool {
+        self.entries.lock().unwrap().contains_key(&opaque)
+    }
+
+    /// Hands a response to the request waiting for it. Returns the response back if
+    /// nothing is waiting for its opaque.
+    pub fn complete(&self, response: Command) -> Option<Command> {
+        match self.remove(response.opaque()) {
+            Some(entry) => {
+                entry.send(Ok(response));
+                None
+            }
+            None => Some(response),
+        }
+    }
+
+    pub fn fail(&self, opaque: i32, error: Error) {
+        if let Some(entry) = self.remove(opaque) {
+            entry.send(Err(error));
+        }
+    }
+
+    /// Fails every entry, e.g. because the connection they were written to is gone.
+    /// Returns the failed requests.
+    pub fn fail_all(&self) -> Vec<AbandonedRequest> {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap());
+        metrics::record_in_flight(&self.addr, 0);
+        entries
+            .into_iter()
+            .map(|(opaque, entry)| {
+                let code = entry.code;
+                entry.send(Err(Error::ConnectionClosed));
+                AbandonedRequest { opaque, code }
+            })
+            .collect()
+    }
+
+    /// Times out a single entry on behalf of a caller that stopped waiting.
+    pub fn expire(&self, opaque: i32) -> Error {
+        let Some(entry) = self.remove(opaque) else {
+            return Error::Timeout {
+                opaque,
+                elapsed: Duration::ZERO,
+            };
+        };
+        let elapsed = entry.started.elapsed();
+        // The caller already gave up, so the error only feeds the metrics.
+        entry.send(Err(Error::Timeout { opaque, elapsed }));
+        Error::Timeout { opaque, elapsed }
+    }
+
+    /// Fails every entry whose deadline passed, or whose caller went away.
+    pub fn sweep(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        let expired: Vec<i32> = entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.started.elapsed() >= entry.timeout || entry.response_tx.is_closed()
+            })
+            .map(|(opaque, _)| *opaque)
+            .collect();
+        for opaque in expired {
+            if let Some(entry) = entries.remove(&opaque) {
+                let elapsed = entry.started.elapsed();
+                entry.send(Err(Error::Timeout { opaque, elapsed }));
+            }
+        }
+        metrics::record_in_flight(&self.addr, entries.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// The response of a request sent with [`Channel::request_async`](super::Channel::request_async).
+///
+/// Resolves to the response, or to the error that ended the request once the connection
+/// failed or the timeout of the call elapsed.
+pub struct ResponseFuture {
+    opaque: i32,
+    response_rx: ResponseReceiver,
+    deadline: Pin<Box<Sleep>>,
+    response_table: Arc<ResponseTable>,
+    /// Lasts as long as the request, which it describes.
+    span: Span,
+}
+
+impl ResponseFuture {
+    pub(crate) fn new(
+        opaque: i32,
+        timeout: Duration,
+        response_rx: ResponseReceiver,
+        response_table: Arc<ResponseTable>,
+        span: Span,
+    ) -> Self {
+        Self {
+            opaque,
+            response_rx,
+            deadline: Box::pin(sleep(timeout)),
+            response_table,
+            span,
+        }
+    }
+
+    pub fn opaque(&self) -> i32 {
+        self.opaque
+    }
+
+    /// Runs `callback` with the result in the background instead of awaiting it.
+    pub fn on_complete<F>(self, callback: F)
+    where
+        F: FnOnce(Result<Command, Error>) + Send + 'static,
+    {
+        tokio::spawn(async move { callback(self.await) });
+    }
+}
+
+impl Future for ResponseFuture {
+    type Output = Result<Command, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = if let Poll::Ready(response) = Pin::new(&mut self.response_rx).poll(cx) {
+            response.unwrap_or(Err(Error::ConnectionClosed))
+        } else if self.deadline.as_mut().poll(cx).is_ready() {
+            Err(self.response_table.expire(self.opaque))
+        } else {
+            return Poll::Pending;
+        };
+        match &result {
+            Ok(response) => debug!(parent: &self.span, code = response.code(), "response"),
+            Err(e) => debug!(parent: &self.span, error = %e, "request failed"),
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture")
+            .field("opaque", &self.opaque)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ResponseTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseTable")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::Semaphore;
+
+    use super::*;
+    use crate::common::code::ResponseCode;
+
+    fn permit() -> OwnedSemaphorePermit {
+        Arc::new(Semaphore::new(1)).try_acquire_owned().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_complete() {
+        let table = ResponseTable::new("127.0.0.1:10911");
+        let response_rx = table.register(1, 0, Duration::from_secs(3), permit());
+        let mut response = Command::new_response(ResponseCode::Success);
+        response.set_opaque(1);
+        assert!(table.complete(response).is_none());
+        assert!(response_rx.await.unwrap().unwrap().is_response());
+        assert_eq!(0, table.len());
+
+        let mut response = Command::new_response(ResponseCode::Success);
+        response.set_opaque(2);
+        assert!(table.complete(response).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_sweep() {
+        let table = ResponseTable::new("127.0.0.1:10911");
+        let expired_rx = table.register(1, 0, Duration::ZERO, permit());
+        let abandoned_rx = table.register(2, 0, Duration::from_secs(3), permit());
+        let _pending_rx = table.register(3, 0, Duration::from_secs(3), permit());
+        drop(abandoned_rx);
+
+        table.sweep();
+        assert!(matches!(
+            expired_rx.await.unwrap(),
+            Err(Error::Timeout { opaque: 1, .. })
+        ));
+        assert!(!table.contains(2));
+        assert!(table.contains(3));
+    }
+
+    #[tokio::test]
+    async fn test_fail_all() {
+        let table = ResponseTable::new("127.0.0.1:10911");
+        let response_rx = table.register(1, 10, Duration::from_secs(3), permit());
+        assert_eq!(
+            vec![AbandonedRequest {
+                opaque: 1,
+                code: 10
+            }],
+            table.fail_all()
+        );
+        assert!(matches!(
+            response_rx.await.unwrap(),
+            Err(Error::ConnectionClosed)
+        ));
+        assert_eq!(0, table.len());
+    }
+}
@@ -0,0 +1,234 @@
+//! JSON bodies of commands, as the Java implementation writes them with fastjson.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::util::Error;
+
+/// Id of the master in [`BrokerData::broker_addrs`].
+pub const MASTER_ID: i64 = 0;
+
+/// Read and write permissions of a queue, `PermName` in Java.
+pub mod perm {
+    pub const PRIORITY: i32 = 0x1 << 3;
+    pub const READ: i32 = 0x1 << 2;
+    pub const WRITE: i32 = 0x1 << 1;
+    pub const INHERIT: i32 = 0x1;
+}
+
+/// Where the queues of a topic live, returned by `GET_ROUTEINFO_BY_TOPIC`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TopicRouteData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_topic_conf: Option<String>,
+    pub queue_datas: Vec<QueueData>,
+    pub broker_datas: Vec<BrokerData>,
+    pub filter_server_table: HashMap<String, Vec<String>>,
+}
+
+/// The queues of a topic on one broker.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct QueueData {
+    pub broker_name: String,
+    pub read_queue_nums: i32,
+    pub write_queue_nums: i32,
+    pub perm: i32,
+    pub topic_sys_flag: i32,
+}
+
+/// A master and its slaves, keyed by broker id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BrokerData {
+    pub cluster: String,
+    pub broker_name: String,
+    pub broker_addrs: HashMap<i64, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone_name: Option<String>,
+    pub enable_acting_master: bool,
+}
+
+impl BrokerData {
+    pub fn master_addr(&self) -> Option<&str> {
+        self.broker_addrs.get(&MASTER_ID).map(String::as_str)
+    }
+}
+
+/// Every broker known to a name server, returned by `GET_BROKER_CLUSTER_INFO`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ClusterInfo {
+    pub broker_addr_table: HashMap<String, BrokerData>,
+    pub cluster_addr_table: HashMap<String, HashSet<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TopicList {
+    pub topic_list: HashSet<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_addr: Option<String>,
+}
+
+/// Body of `REGISTER_BROKER`: the topics a broker serves.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RegisterBrokerBody {
+    pub filter_server_list: Vec<String>,
+    pub topic_config_serialize_wrapper: TopicConfigSerializeWrapper,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TopicConfigSerializeWrapper {
+    pub data_version: DataVersion,
+    pub topic_config_table: HashMap<String, TopicConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DataVersion {
+    pub counter: i64,
+    pub state_version: i64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TopicConfig {
+    pub topic_name: String,
+    pub read_queue_nums: i32,
+    pub write_queue_nums: i32,
+    pub perm: i32,
+    pub topic_filter_type: String,
+    pub topic_sys_flag: i32,
+    pub order: bool,
+    pub attributes: HashMap<String, String>,
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            topic_name: String::new(),
+            read_queue_nums: 16,
+            write_queue_nums: 16,
+            perm: perm::READ | perm::WRITE,
+            topic_filter_type: "SINGLE_TAG".to_string(),
+            topic_sys_flag: 0,
+            order: false,
+            attributes: HashMap::new(),
+        }
+    }
+}
+
+/// `KVTable` in Java, e.g. the broker config a name server returns on registration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KvTable {
+    pub table: HashMap<String, String>,
+}
+
+pub(crate) fn encode<T: Serialize>(body: &T) -> Vec<u8> {
+    serde_json::to_vec(body).unwrap()
+}
+
+/// Decodes a body written by fastjson, which may leave the keys of maps with numeric
+/// keys unquoted, e.g. `{"brokerAddrs":{0:"127.0.0.1:10911"}}`.
+pub(crate) fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(body)
+        .or_else(|_| serde_json::from_slice(&quote_numeric_keys(body)))
+        .map_err(|e| Error::DecodeCommandError(format!("bad json body: {}", e)))
+}
+
+fn quote_numeric_keys(json: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(json.len() + 16);
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < json.len() {
+        let b = json[i];
+        result.push(b);
+        i += 1;
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' | b',' => {
+                // Looks for `-?[0-9]+` followed by a colon.
+                let key_start = i + json[i..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_whitespace())
+                    .count();
+                let digits_start = key_start + usize::from(json.get(key_start) == Some(&b'-'));
+                let key_end = digits_start
+                    + json[digits_start..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_digit())
+                        .count();
+                let colon = key_end
+                    + json[key_end..]
+                        .iter()
+                        .take_while(|b| b.is_ascii_whitespace())
+                        .count();
+                if key_end > digits_start && json.get(colon) == Some(&b':') {
+                    result.extend_from_slice(&json[i..key_start]);
+                    result.push(b'"');
+                    result.extend_from_slice(&json[key_start..key_end]);
+                    result.push(b'"');
+                    i = key_end;
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As written by a 4.x name server, which doesn't honor `acceptStandardJsonOnly`.
+    const FASTJSON_ROUTE: &[u8] = b"{\"brokerDatas\":[{\"brokerAddrs\":{0:\"127.0.0.1:10911\",\
+1:\"127.0.0.1:10921\"},\"brokerName\":\"broker-a\",\"cluster\":\"DefaultCluster\"}],\
+\"filterServerTable\":{},\"queueDatas\":[{\"brokerName\":\"broker-a\",\"perm\":6,\
+\"readQueueNums\":4,\"topicSysFlag\":0,\"writeQueueNums\":4}]}";
+
+    #[test]
+    fn test_decode_fastjson_route() {
+        let route: TopicRouteData = decode(FASTJSON_ROUTE).unwrap();
+        assert_eq!(1, route.broker_datas.len());
+        let broker = &route.broker_datas[0];
+        assert_eq!("DefaultCluster", broker.cluster);
+        assert_eq!(Some("127.0.0.1:10911"), broker.master_addr());
+        assert_eq!("127.0.0.1:10921", broker.broker_addrs[&1]);
+        assert_eq!(
+            QueueData {
+                broker_name: "broker-a".to_string(),
+                read_queue_nums: 4,
+                write_queue_nums: 4,
+                perm: perm::READ | perm::WRITE,
+                topic_sys_flag: 0,
+            },
+            route.queue_datas[0]
+        );
+    }
+
+    #[test]
+    fn test_quote_numeric_keys() {
+        assert_eq!(
+            br#"{"0":"a", "-1" :[1,2,{"3":"{4:5}"}]}"#.to_vec(),
+            quote_numeric_keys(br#"{0:"a", -1 :[1,2,{3:"{4:5}"}]}"#)
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt;
+
+use crate::util::Error;
+
+/// Declares a code enum together with its conversions from and to the wire value.
+macro_rules! codes {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = $value:expr,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            pub fn code(&self) -> i32 {
+                match self {
+                    $($name::$variant => $value,)*
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(code: $name) -> i32 {
+                code.code()
+            }
+        }
+
+        impl TryFrom<i32> for $name {
+            type Error = Error;
+
+            fn try_from(code: i32) -> Result<Self, Self::Error> {
+                match code {
+                    $($value => Ok($name::$variant),)*
+                    _ => Err(Error::UnknownCode(code)),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:?}({})", self, self.code())
+            }
+        }
+    };
+}
+
+codes! {
+    /// Codes of the requests defined by the RocketMQ remoting protocol.
+    RequestCode {
+        SendMessage = 10,
+        PullMessage = 11,
+        QueryMessage = 12,
+        QueryBrokerOffset = 13,
+        QueryConsumerOffset = 14,
+        UpdateConsumerOffset = 15,
+        UpdateAndCreateTopic = 17,
+        UpdateAndCreateTopicList = 18,
+        GetAllTopicConfig = 21,
+        GetTopicConfigList = 22,
+        GetTopicNameList = 23,
+        UpdateBrokerConfig = 25,
+        GetBrokerConfig = 26,
+        TriggerDeleteFiles = 27,
+        GetBrokerRuntimeInfo = 28,
+        SearchOffsetByTimestamp = 29,
+        GetMaxOffset = 30,
+        GetMinOffset = 31,
+        GetEarliestMsgStoretime = 32,
+        ViewMessageById = 33,
+        HeartBeat = 34,
+        UnregisterClient = 35,
+        ConsumerSendMsgBack = 36,
+        EndTransaction = 37,
+        GetConsumerListByGroup = 38,
+        CheckTransactionState = 39,
+        NotifyConsumerIdsChanged = 40,
+        LockBatchMq = 41,
+        UnlockBatchMq = 42,
+        GetAllConsumerOffset = 43,
+        GetAllDelayOffset = 45,
+        CheckClientConfig = 46,
+        UpdateAndCreateAclConfig = 50,
+        DeleteAclConfig = 51,
+        GetBrokerClusterAclInfo = 52,
+        UpdateGlobalWhiteAddrsConfig = 53,
+        GetBrokerClusterAclConfig = 54,
+        GetTimerCheckPoint = 60,
+        GetTimerMetrics = 61,
+        PutKvConfig = 100,
+        GetKvConfig = 101,
+        DeleteKvConfig = 102,
+        RegisterBroker = 103,
+        UnregisterBroker = 104,
+        GetRouteInfoByTopic = 105,
+        GetBrokerClusterInfo = 106,
+        UpdateAndCreateSubscriptionGroup = 200,
+        GetAllSubscriptionGroupConfig = 201,
+        GetTopicStatsInfo = 202,
+        GetConsumerConnectionList = 203,
+        GetProducerConnectionList = 204,
+        WipeWritePermOfBroker = 205,
+        GetAllTopicListFromNameServer = 206,
+        DeleteSubscriptionGroup = 207,
+        GetConsumeStats = 208,
+        SuspendConsumer = 209,
+        ResumeConsumer = 210,
+        ResetConsumerOffsetInConsumer = 211,
+        ResetConsumerOffsetInBroker = 212,
+        AdjustConsumerThreadPool = 213,
+        WhoConsumeTheMessage = 214,
+        DeleteTopicInBroker = 215,
+        DeleteTopicInNamesrv = 216,
+        GetKvListByNamespace = 219,
+        ResetConsumerClientOffset = 220,
+        GetConsumerStatusFromClient = 221,
+        InvokeBrokerToResetOffset = 222,
+        InvokeBrokerToGetConsumerStatus = 223,
+        GetTopicsByCluster = 224,
+        QueryTopicConsumeByWho = 300,
+        RegisterFilterServer = 301,
+        RegisterMessageFilterClass = 302,
+        QueryConsumeTimeSpan = 303,
+        GetSystemTopicListFromNs = 304,
+        GetSystemTopicListFromBroker = 305,
+        CleanExpiredConsumeQueue = 306,
+        GetConsumerRunningInfo = 307,
+        QueryCorrectionOffset = 308,
+        ConsumeMessageDirectly = 309,
+        SendMessageV2 = 310,
+        GetUnitTopicList = 311,
+        GetHasUnitSubTopicList = 312,
+        GetHasUnitSubUnunitTopicList = 313,
+        CloneGroupOffset = 314,
+        ViewBrokerStatsData = 315,
+        CleanUnusedTopic = 316,
+        GetBrokerConsumeStats = 317,
+        UpdateNamesrvConfig = 318,
+        GetNamesrvConfig = 319,
+        SendBatchMessage = 320,
+        QueryConsumeQueue = 321,
+        QueryDataVersion = 322,
+        ResumeCheckHalfMessage = 323,
+        SendReplyMessage = 324,
+        SendReplyMessageV2 = 325,
+        PushReplyMessageToClient = 326,
+        AddWritePermOfBroker = 327,
+        GetAllProducerInfo = 328,
+        DeleteExpiredCommitlog = 329,
+        QueryTopicsByConsumer = 343,
+        QuerySubscriptionByConsumer = 345,
+        GetTopicConfig = 351,
+        GetSubscriptionGroupConfig = 352,
+        UpdateAndGetGroupForbidden = 353,
+        LitePullMessage = 361,
+        QueryAssignment = 400,
+        SetMessageRequestMode = 401,
+        GetAllMessageRequestMode = 402,
+        UpdateAndCreateStaticTopic = 513,
+        GetBrokerMemberGroup = 901,
+        AddBroker = 902,
+        RemoveBroker = 903,
+        BrokerHeartbeat = 904,
+        NotifyMinBrokerIdChange = 905,
+        ExchangeBrokerHaInfo = 906,
+        GetBrokerHaStatus = 907,
+        ResetMasterFlushOffset = 908,
+        PopMessage = 200050,
+        AckMessage = 200051,
+        PeekMessage = 200052,
+        ChangeMessageInvisibleTime = 200053,
+        Notification = 200054,
+        PollingInfo = 200055,
+        BatchAckMessage = 200151,
+    }
+}
+
+codes! {
+    /// Codes carried by responses, including the remoting-level system codes.
+    ResponseCode {
+        Success = 0,
+        SystemError = 1,
+        SystemBusy = 2,
+        RequestCodeNotSupported = 3,
+        TransactionFailed = 4,
+        FlushDiskTimeout = 10,
+        SlaveNotAvailable = 11,
+        FlushSlaveTimeout = 12,
+        MessageIllegal = 13,
+        ServiceNotAvailable = 14,
+        VersionNotSupported = 15,
+        NoPermission = 16,
+        TopicNotExist = 17,
+        TopicExistAlready = 18,
+        PullNotFound = 19,
+        PullRetryImmediately = 20,
+        PullOffsetMoved = 21,
+        QueryNotFound = 22,
+        SubscriptionParseFailed = 23,
+        SubscriptionNotExist = 24,
+        SubscriptionNotLatest = 25,
+        SubscriptionGroupNotExist = 26,
+        FilterDataNotExist = 27,
+        FilterDataNotLatest = 28,
+        TransactionShouldCommit = 200,
+        TransactionShouldRollback = 201,
+        TransactionStateUnknow = 202,
+        TransactionStateGroupWrong = 203,
+        NoBuyerId = 204,
+        NotInCurrentUnit = 205,
+        ConsumerNotOnline = 206,
+        ConsumeMsgTimeout = 207,
+        NoMessage = 208,
+        PollingFull = 209,
+        PollingTimeout = 210,
+        BrokerNotExist = 211,
+        BrokerDispatchNotComplete = 212,
+        BroadcastConsumption = 213,
+        FlowControl = 215,
+        NotLeaderForQueue = 501,
+        IllegalOperation = 604,
+        RpcUnknown = -1000,
+        RpcAddrIsNull = -1002,
+        RpcSendToChannelFailed = -1004,
+        RpcTimeOut = -1006,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_conversion() {
+        assert_eq!(105, i32::from(RequestCode::GetRouteInfoByTopic));
+        assert_eq!(400, RequestCode::QueryAssignment.code());
+        assert_eq!(
+            RequestCode::PopMessage,
+            RequestCode::try_from(200050).unwrap()
+        );
+        assert_eq!(
+            ResponseCode::TopicNotExist,
+            ResponseCode::try_from(17).unwrap()
+        );
+        assert_eq!(
+            ResponseCode::RpcTimeOut,
+            ResponseCode::try_from(-1006).unwrap()
+        );
+        assert!(matches!(
+            RequestCode::try_from(99999),
+            Err(Error::UnknownCode(99999))
+        ));
+    }
+}
@@ -1,52 +1,247 @@
 use std::{
     collections::HashMap,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicI32, Ordering},
 };
 
+use bytes::{BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 
 use crate::util::{self, Error};
 
-static REQUEST_ID: AtomicUsize = AtomicUsize::new(0);
+use super::{
+    code::{RequestCode, ResponseCode},
+    header::CommandCustomHeader,
+    rocketmq_serializable,
+};
+
+static REQUEST_ID: AtomicI32 = AtomicI32::new(0);
+
+/// Bit of `flag` that marks a command as a response.
+const RPC_TYPE: i32 = 0;
+/// Bit of `flag` that marks a request the peer must not answer.
+const RPC_ONEWAY: i32 = 1;
+
+/// Language of the peer that produced a command, serialized by name in JSON headers.
+/// Names this side doesn't know about decode as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+#[repr(u8)]
+pub enum LanguageCode {
+    Java = 0,
+    Cpp = 1,
+    Dotnet = 2,
+    Python = 3,
+    Delphi = 4,
+    Erlang = 5,
+    Ruby = 6,
+    Http = 8,
+    Go = 9,
+    Php = 10,
+    Oms = 11,
+    Rust = 12,
+    #[serde(other)]
+    Other = 7,
+}
+
+impl LanguageCode {
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
 
-#[derive(Serialize, Deserialize)]
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0 => LanguageCode::Java,
+            1 => LanguageCode::Cpp,
+            2 => LanguageCode::Dotnet,
+            3 => LanguageCode::Python,
+            4 => LanguageCode::Delphi,
+            5 => LanguageCode::Erlang,
+            6 => LanguageCode::Ruby,
+            8 => LanguageCode::Http,
+            9 => LanguageCode::Go,
+            10 => LanguageCode::Php,
+            11 => LanguageCode::Oms,
+            12 => LanguageCode::Rust,
+            _ => LanguageCode::Other,
+        }
+    }
+}
+
+/// How the header of a command is serialized on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SerializeType {
+    Json,
+    Rocketmq,
+}
+
+impl SerializeType {
+    pub fn code(&self) -> u8 {
+        match self {
+            SerializeType::Json => 0,
+            SerializeType::Rocketmq => 1,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(SerializeType::Json),
+            1 => Some(SerializeType::Rocketmq),
+            _ => None,
+        }
+    }
+}
+
+/// The command header, laid out the way the Java implementation serializes it:
+/// fastjson writes the keys in alphabetical order and omits null fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Header {
-    code: u8,
-    flag: u8,
-    language: u8,
-    opaque: usize,
-    remark: String,
-    ext_fields: HashMap<String, String>,
+    pub(crate) code: i32,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) ext_fields: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) flag: i32,
+    pub(crate) language: LanguageCode,
+    pub(crate) opaque: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) remark: Option<String>,
+    #[serde(rename = "serializeTypeCurrentRPC", default = "default_serialize_type")]
+    pub(crate) serialize_type_current_rpc: SerializeType,
+    #[serde(default)]
+    pub(crate) version: i32,
 }
 
+fn default_serialize_type() -> SerializeType {
+    SerializeType::Json
+}
+
+#[derive(Debug, Clone)]
 pub struct Command {
     header: Header,
-    body: Option<Vec<u8>>,
+    body: Option<Bytes>,
 }
 
 impl Command {
-    pub fn new(code: u8) -> Self {
+    pub fn new(code: impl Into<i32>) -> Self {
         Self {
             header: Header {
-                code,
+                code: code.into(),
+                ext_fields: HashMap::new(),
                 flag: 0,
-                language: 12,
+                language: LanguageCode::Rust,
                 opaque: REQUEST_ID.fetch_add(1, Ordering::Relaxed),
-                remark: "".to_string(),
-                ext_fields: HashMap::new(),
+                remark: None,
+                serialize_type_current_rpc: SerializeType::Json,
+                version: 0,
             },
             body: None,
         }
     }
 
-    pub fn code(&self) -> u8 {
+    /// Creates a response command. Its opaque must be set to the one of the request it answers.
+    pub fn new_response(code: impl Into<i32>) -> Self {
+        let mut command = Self::new(code);
+        command.mark_response_type();
+        command
+    }
+
+    pub fn code(&self) -> i32 {
         self.header.code
     }
 
-    pub fn opaque(&self) -> usize {
+    /// The request code, or `None` for responses and codes this crate doesn't know.
+    pub fn request_code(&self) -> Option<RequestCode> {
+        if self.is_response() {
+            return None;
+        }
+        RequestCode::try_from(self.header.code).ok()
+    }
+
+    /// The response code, or `None` for requests and codes this crate doesn't know.
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        if !self.is_response() {
+            return None;
+        }
+        ResponseCode::try_from(self.header.code).ok()
+    }
+
+    /// Passes a `SUCCESS` response through, and turns any other into
+    /// [`Error::RemoteError`] with its code and remark.
+    pub fn ensure_success(self) -> Result<Self, Error> {
+        if self.response_code() == Some(ResponseCode::Success) {
+            return Ok(self);
+        }
+        Err(Error::RemoteError {
+            code: self.header.code,
+            remark: self.header.remark.unwrap_or_default(),
+        })
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.header.flag & (1 << RPC_TYPE) != 0
+    }
+
+    pub fn mark_response_type(&mut self) {
+        self.header.flag |= 1 << RPC_TYPE;
+    }
+
+    pub fn is_oneway(&self) -> bool {
+        self.header.flag & (1 << RPC_ONEWAY) != 0
+    }
+
+    pub fn mark_oneway(&mut self) {
+        self.header.flag |= 1 << RPC_ONEWAY;
+    }
+
+    pub fn opaque(&self) -> i32 {
         self.header.opaque
     }
 
+    pub fn set_opaque(&mut self, opaque: i32) {
+        self.header.opaque = opaque;
+    }
+
+    pub fn flag(&self) -> i32 {
+        self.header.flag
+    }
+
+    pub fn set_flag(&mut self, flag: i32) {
+        self.header.flag = flag;
+    }
+
+    pub fn language(&self) -> LanguageCode {
+        self.header.language
+    }
+
+    pub fn set_language(&mut self, language: LanguageCode) {
+        self.header.language = language;
+    }
+
+    pub fn version(&self) -> i32 {
+        self.header.version
+    }
+
+    pub fn set_version(&mut self, version: i32) {
+        self.header.version = version;
+    }
+
+    pub fn remark(&self) -> Option<&str> {
+        self.header.remark.as_deref()
+    }
+
+    pub fn set_remark(&mut self, remark: impl Into<String>) {
+        self.header.remark = Some(remark.into());
+    }
+
+    pub fn serialize_type(&self) -> SerializeType {
+        self.header.serialize_type_current_rpc
+    }
+
+    pub fn set_serialize_type(&mut self, serialize_type: SerializeType) {
+        self.header.serialize_type_current_rpc = serialize_type;
+    }
+
     pub fn add_property(&mut self, key: impl Into<String>, value: impl Into<String>) {
         self.header.ext_fields.insert(key.into(), value.into());
     }
@@ -55,52 +250,113 @@ impl Command {
         self.header.ext_fields.get(key)
     }
 
-    pub fn set_body(&mut self, body: Vec<u8>) {
-        self.body = Some(body);
+    pub fn properties(&self) -> &HashMap<String, String> {
+        &self.header.ext_fields
     }
 
-    pub fn body(&self) -> Option<&[u8]> {
-        self.body.as_ref().map(|v| v.as_ref())
+    /// Writes a custom header into the ext fields of this command.
+    pub fn with_header<H: CommandCustomHeader>(mut self, header: &H) -> Self {
+        header.encode(&mut self.header.ext_fields);
+        self
     }
 
-    pub fn encode(self) -> Vec<u8> {
-        let mut length: u32 = 8;
+    /// Reads a custom header back from the ext fields of this command.
+    pub fn decode_header<H: CommandCustomHeader>(&self) -> Result<H, Error> {
+        H::decode(&self.header.ext_fields)
+    }
 
-        let header_data = serde_json::to_vec(&self.header).unwrap();
-        length = length + header_data.len() as u32;
+    pub fn set_body(&mut self, body: impl Into<Bytes>) {
+        self.body = Some(body.into());
+    }
 
-        let ref_body = self.body.as_ref();
-        if let Some(body) = ref_body {
-            length = length + body.len() as u32;
-        }
-        let mut result = Vec::with_capacity(4 + length as usize);
-        result.extend(Self::u32_to_vec(length));
-        result.extend(Self::u32_to_vec(header_data.len() as u32));
-        result.extend(header_data);
-        if let Some(body) = self.body {
-            result.extend(body);
-        }
+    /// The body, sharing its memory with the frame it was read from.
+    pub fn body(&self) -> Option<&Bytes> {
+        self.body.as_ref()
+    }
 
+    /// Encodes the command into a frame:
+    /// `length(4) | serialize type(1) + header length(3) | header | body`,
+    /// where `length` counts everything after itself.
+    pub fn encode(self) -> Vec<u8> {
+        let mut header = BytesMut::new();
+        self.encode_header(&mut header);
+        let body = self.body.unwrap_or_default();
+        let mut result = Vec::with_capacity(header.len() + body.len());
+        result.extend_from_slice(&header);
+        result.extend_from_slice(&body);
         result
     }
 
-    fn u32_to_vec(data: u32) -> Vec<u8> {
-        let mut result = Vec::with_capacity(4);
-        result.push((data >> 24) as u8);
-        result.push((data >> 16) as u8);
-        result.push((data >> 8) as u8);
-        result.push(data as u8);
+    /// Writes everything of the frame but the body, which can then be written from
+    /// [`Command::body`] without copying it.
+    pub fn encode_header(&self, dst: &mut BytesMut) {
+        let header_data = match self.header.serialize_type_current_rpc {
+            SerializeType::Json => serde_json::to_vec(&self.header).unwrap(),
+            SerializeType::Rocketmq => rocketmq_serializable::encode_header(&self.header),
+        };
+        let body_length = self.body.as_ref().map_or(0, |body| body.len());
+        let length = 4 + header_data.len() + body_length;
+
+        dst.reserve(8 + header_data.len());
+        dst.put_u32(length as u32);
+        dst.put_slice(&Self::mark_serialize_type(
+            header_data.len() as u32,
+            self.header.serialize_type_current_rpc,
+        ));
+        dst.put_slice(&header_data);
+    }
+
+    fn mark_serialize_type(header_length: u32, serialize_type: SerializeType) -> [u8; 4] {
+        let mut result = (header_length & 0x00FF_FFFF).to_be_bytes();
+        result[0] = serialize_type.code();
         result
     }
 
+    /// Decodes a frame produced by [`Command::encode`], including its leading length field.
     pub fn decode(data: &[u8]) -> Result<Self, Error> {
-        let length = util::vec_to_u32(&data);
-        let header_length = util::vec_to_u32(&data[4..8]);
-        let header: Header = serde_json::from_slice(&data[8..8 + header_length as usize])
-            .map_err(|_| Error::DecodeCommandError)?;
+        Self::decode_bytes(Bytes::copy_from_slice(data))
+    }
+
+    /// Like [`Command::decode`], but the body is a slice of `data` instead of a copy.
+    /// Frames that are truncated or whose lengths don't add up are rejected instead of
+    /// read out of bounds.
+    pub fn decode_bytes(data: Bytes) -> Result<Self, Error> {
+        if data.len() < 8 {
+            return Err(Error::DecodeCommandError(format!(
+                "frame of {} bytes is shorter than its length fields",
+                data.len()
+            )));
+        }
+        let length = util::vec_to_u32(&data) as usize;
+        if length != data.len() - 4 {
+            return Err(Error::DecodeCommandError(format!(
+                "frame length {} doesn't match the {} bytes after it",
+                length,
+                data.len() - 4
+            )));
+        }
+        let marked_length = util::vec_to_u32(&data[4..8]);
+        let serialize_type =
+            SerializeType::from_code((marked_length >> 24) as u8).ok_or_else(|| {
+                Error::DecodeCommandError(format!("unknown serialize type {}", marked_length >> 24))
+            })?;
+        let header_length = (marked_length & 0x00FF_FFFF) as usize;
+        let Some(header_data) = data.get(8..8 + header_length) else {
+            return Err(Error::DecodeCommandError(format!(
+                "header length {} exceeds the frame length {}",
+                header_length, length
+            )));
+        };
+        let mut header: Header = match serialize_type {
+            SerializeType::Json => serde_json::from_slice(header_data)
+                .map_err(|e| Error::DecodeCommandError(format!("bad json header: {}", e)))?,
+            SerializeType::Rocketmq => rocketmq_serializable::decode_header(header_data)?,
+        };
+        header.serialize_type_current_rpc = serialize_type;
+        let body = data.slice(8 + header_length..);
         Ok(Self {
             header,
-            body: Some(data[8 + header_length as usize..length as usize].to_vec()),
+            body: if body.is_empty() { None } else { Some(body) },
         })
     }
 }
@@ -108,18 +364,165 @@ impl Command {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::header::broker::{
+        QueryConsumerOffsetRequestHeader, QueryConsumerOffsetResponseHeader,
+    };
+
+    // Frames laid out as the Java 5.x remoting implementation writes them.
+    const GOLDEN_ROUTE_REQUEST: &[u8] = b"\x00\x00\x00\x87\x00\x00\x00\x83\
+{\"code\":105,\"extFields\":{\"topic\":\"TopicTest\"},\"flag\":0,\"language\":\"JAVA\",\
+\"opaque\":7,\"serializeTypeCurrentRPC\":\"JSON\",\"version\":475}";
+
+    const GOLDEN_ERROR_RESPONSE: &[u8] = b"\x00\x00\x00\xab\x00\x00\x00\xa7\
+{\"code\":17,\"flag\":1,\"language\":\"JAVA\",\"opaque\":7,\
+\"remark\":\"No topic route info in name server for the topic: TopicTest\",\
+\"serializeTypeCurrentRPC\":\"JSON\",\"version\":475}";
+
+    const GOLDEN_BODY_RESPONSE: &[u8] = b"\x00\x00\x00\x85\x00\x00\x00\x5f\
+{\"code\":0,\"flag\":1,\"language\":\"JAVA\",\"opaque\":8,\
+\"serializeTypeCurrentRPC\":\"JSON\",\"version\":475}\
+{\"brokerDatas\":[],\"queueDatas\":[]}";
 
     #[test]
     fn test_encode_decode() {
         let mut command = Command::new(1);
         command.add_property("test-key", "value");
         command.set_body(vec![1, 2, 3]);
+        let opaque = command.opaque();
 
         let encoded = command.encode();
         let decoded = Command::decode(&encoded).unwrap();
         assert_eq!(1, decoded.code());
-        assert_eq!(0, decoded.opaque());
+        assert_eq!(opaque, decoded.opaque());
         assert_eq!("value", decoded.get_property("test-key").unwrap());
-        assert_eq!(vec![1, 2, 3], decoded.body().unwrap());
+        assert_eq!(&[1, 2, 3][..], decoded.body().unwrap());
+    }
+
+    #[test]
+    fn test_decode_golden_request() {
+        let decoded = Command::decode(GOLDEN_ROUTE_REQUEST).unwrap();
+        assert_eq!(105, decoded.code());
+        assert_eq!(0, decoded.flag());
+        assert!(!decoded.is_response());
+        assert_eq!(
+            Some(RequestCode::GetRouteInfoByTopic),
+            decoded.request_code()
+        );
+        assert_eq!(None, decoded.response_code());
+        assert_eq!(LanguageCode::Java, decoded.language());
+        assert_eq!(7, decoded.opaque());
+        assert_eq!(475, decoded.version());
+        assert_eq!("TopicTest", decoded.get_property("topic").unwrap());
+        assert!(decoded.remark().is_none());
+        assert!(decoded.body().is_none());
+    }
+
+    #[test]
+    fn test_decode_golden_responses() {
+        let decoded = Command::decode(GOLDEN_ERROR_RESPONSE).unwrap();
+        assert_eq!(17, decoded.code());
+        assert!(decoded.is_response());
+        assert_eq!(Some(ResponseCode::TopicNotExist), decoded.response_code());
+        assert_eq!(None, decoded.request_code());
+        assert_eq!(
+            "No topic route info in name server for the topic: TopicTest",
+            decoded.remark().unwrap()
+        );
+
+        let decoded = Command::decode(GOLDEN_BODY_RESPONSE).unwrap();
+        assert_eq!(0, decoded.code());
+        assert_eq!(8, decoded.opaque());
+        assert_eq!(
+            &b"{\"brokerDatas\":[],\"queueDatas\":[]}"[..],
+            decoded.body().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_matches_golden() {
+        let mut command = Command::new(RequestCode::GetRouteInfoByTopic);
+        command.set_language(LanguageCode::Java);
+        command.set_opaque(7);
+        command.set_version(475);
+        command.add_property("topic", "TopicTest");
+        assert_eq!(GOLDEN_ROUTE_REQUEST, command.encode());
+
+        let mut command = Command::new_response(ResponseCode::TopicNotExist);
+        command.set_language(LanguageCode::Java);
+        command.set_opaque(7);
+        command.set_version(475);
+        command.set_remark("No topic route info in name server for the topic: TopicTest");
+        assert_eq!(GOLDEN_ERROR_RESPONSE, command.encode());
+
+        let mut command = Command::new_response(ResponseCode::Success);
+        command.set_language(LanguageCode::Java);
+        command.set_opaque(8);
+        command.set_version(475);
+        command.set_body(b"{\"brokerDatas\":[],\"queueDatas\":[]}".to_vec());
+        assert_eq!(GOLDEN_BODY_RESPONSE, command.encode());
+    }
+
+    #[test]
+    fn test_encode_decode_rocketmq() {
+        let mut command = Command::new(105);
+        command.set_serialize_type(SerializeType::Rocketmq);
+        command.set_remark("remark");
+        command.add_property("topic", "TopicTest");
+        command.set_body(vec![1, 2, 3]);
+        let opaque = command.opaque();
+
+        let encoded = command.encode();
+        assert_eq!(SerializeType::Rocketmq.code(), encoded[4]);
+        let decoded = Command::decode(&encoded).unwrap();
+        assert_eq!(105, decoded.code());
+        assert_eq!(opaque, decoded.opaque());
+        assert_eq!(LanguageCode::Rust, decoded.language());
+        assert_eq!(SerializeType::Rocketmq, decoded.serialize_type());
+        assert_eq!("remark", decoded.remark().unwrap());
+        assert_eq!("TopicTest", decoded.get_property("topic").unwrap());
+        assert_eq!(&[1, 2, 3][..], decoded.body().unwrap());
+    }
+
+    #[test]
+    fn test_decode_unknown_serialize_type() {
+        let mut frame = Command::new(0).encode();
+        frame[4] = 2;
+        assert!(matches!(
+            Command::decode(&frame),
+            Err(Error::DecodeCommandError(_))
+        ));
+    }
+
+    #[test]
+    fn test_custom_header() {
+        let command = Command::new(RequestCode::QueryConsumerOffset).with_header(
+            &QueryConsumerOffsetRequestHeader {
+                consumer_group: "group".to_string(),
+                topic: "TopicTest".to_string(),
+                queue_id: 1,
+                set_zero_if_not_found: Some(false),
+            },
+        );
+        assert_eq!("1", command.get_property("queueId").unwrap());
+
+        let decoded = Command::decode(&command.encode()).unwrap();
+        let header: QueryConsumerOffsetRequestHeader = decoded.decode_header().unwrap();
+        assert_eq!("group", header.consumer_group);
+        assert_eq!(Some(false), header.set_zero_if_not_found);
+        assert!(matches!(
+            decoded.decode_header::<QueryConsumerOffsetResponseHeader>(),
+            Err(Error::MissingHeaderField("offset"))
+        ));
+    }
+
+    #[test]
+    fn test_decode_unknown_language() {
+        let header = br#"{"code":0,"flag":1,"language":"KOTLIN","opaque":1}"#;
+        let mut frame = Vec::new();
+        frame.extend((4 + header.len() as u32).to_be_bytes());
+        frame.extend((header.len() as u32).to_be_bytes());
+        frame.extend(header);
+        let decoded = Command::decode(&frame).unwrap();
+        assert_eq!(LanguageCode::Other, decoded.language());
     }
 }
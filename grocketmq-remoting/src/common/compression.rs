@@ -0,0 +1,240 @@
+//! Compression of message bodies, `CompressionType` and `Compressor` in Java.
+
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::util::Error;
+
+use super::{header::broker::SendMessageRequestHeader, message::sys_flag};
+
+/// Largest body decompressed by default, the default `maxMessageSize` of Java producers
+/// and brokers.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// The algorithm a body was compressed with, stored in bits 8 to 10 of `sysFlag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    Lz4,
+    Zstd,
+    Zlib,
+}
+
+impl CompressionType {
+    /// The compression type bits of `sysFlag`.
+    pub fn sys_flag(&self) -> i32 {
+        match self {
+            CompressionType::Lz4 => sys_flag::COMPRESSION_LZ4_TYPE,
+            CompressionType::Zstd => sys_flag::COMPRESSION_ZSTD_TYPE,
+            CompressionType::Zlib => sys_flag::COMPRESSION_ZLIB_TYPE,
+        }
+    }
+
+    /// The type a compressed body was compressed with. Brokers before 5.0 only knew
+    /// zlib and leave the type bits unset.
+    pub fn from_sys_flag(flag: i32) -> Result<Self, Error> {
+        match flag & sys_flag::COMPRESSION_TYPE_COMPARATOR {
+            0 | sys_flag::COMPRESSION_ZLIB_TYPE => Ok(CompressionType::Zlib),
+            sys_flag::COMPRESSION_LZ4_TYPE => Ok(CompressionType::Lz4),
+            sys_flag::COMPRESSION_ZSTD_TYPE => Ok(CompressionType::Zstd),
+            bits => Err(Error::DecodeCommandError(format!(
+                "unknown compression type {}",
+                bits >> 8
+            ))),
+        }
+    }
+
+    /// Compresses `data` in the stream format the Java client writes. `level` applies to
+    /// zlib and zstd only.
+    pub fn compress(&self, data: &[u8], level: i32) -> Result<Vec<u8>, Error> {
+        match self {
+            CompressionType::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder
+                    .finish()
+                    .map_err(|e| Error::IoError(std::io::Error::other(e)))
+            }
+            CompressionType::Zstd => Ok(zstd::encode_all(data, level)?),
+            CompressionType::Zlib => {
+                let mut encoder =
+                    ZlibEncoder::new(Vec::new(), Compression::new(level.clamp(0, 9) as u32));
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    /// Decompresses `data`, failing once the result grows past `max_size` bytes rather
+    /// than letting a small body expand without bounds.
+    pub fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+        let mut result = Vec::with_capacity((data.len() * 2).min(max_size));
+        let limit = max_size as u64 + 1;
+        let read = match self {
+            CompressionType::Lz4 => lz4_flex::frame::FrameDecoder::new(data)
+                .take(limit)
+                .read_to_end(&mut result),
+            CompressionType::Zstd => {
+                zstd::Decoder::new(data).and_then(|d| d.take(limit).read_to_end(&mut result))
+            }
+            CompressionType::Zlib => ZlibDecoder::new(data).take(limit).read_to_end(&mut result),
+        };
+        read.map_err(|e| {
+            Error::DecodeCommandError(format!("bad {:?} compressed body: {}", self, e))
+        })?;
+        if result.len() > max_size {
+            return Err(Error::DecodeCommandError(format!(
+                "{:?} compressed body expands past the limit of {} bytes",
+                self, max_size
+            )));
+        }
+        Ok(result)
+    }
+}
+
+/// Compresses the bodies a producer sends once they exceed a threshold,
+/// `compressMsgBodyOverHowmuch` in Java. Batches are sent uncompressed, like in Java.
+#[derive(Debug, Clone)]
+pub struct MessageCompressor {
+    pub compression_type: CompressionType,
+    pub level: i32,
+    /// Bodies larger than this many bytes are compressed.
+    pub threshold: usize,
+}
+
+impl Default for MessageCompressor {
+    fn default() -> Self {
+        Self {
+            compression_type: CompressionType::Zlib,
+            level: 5,
+            threshold: 4 * 1024,
+        }
+    }
+}
+
+impl MessageCompressor {
+    /// Compresses `body` if it is over the threshold and smaller for it, and marks the
+    /// `sys_flag` of `header` accordingly. Returns the body to send.
+    pub fn compress(
+        &self,
+        header: &mut SendMessageRequestHeader,
+        body: Bytes,
+    ) -> Result<Bytes, Error> {
+        if body.len() <= self.threshold {
+            return Ok(body);
+        }
+        let compressed = self.compression_type.compress(&body, self.level)?;
+        if compressed.len() >= body.len() {
+            return Ok(body);
+        }
+        header.sys_flag = (header.sys_flag & !sys_flag::COMPRESSION_TYPE_COMPARATOR)
+            | sys_flag::COMPRESSED
+            | self.compression_type.sys_flag();
+        Ok(compressed.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [CompressionType; 3] = [
+        CompressionType::Lz4,
+        CompressionType::Zstd,
+        CompressionType::Zlib,
+    ];
+
+    #[test]
+    fn test_round_trip() {
+        let data = "Hello RocketMQ ".repeat(1000);
+        for compression_type in TYPES {
+            let compressed = compression_type.compress(data.as_bytes(), 5).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(
+                data.as_bytes(),
+                compression_type
+                    .decompress(&compressed, DEFAULT_MAX_BODY_SIZE)
+                    .unwrap()
+            );
+            assert_eq!(
+                compression_type,
+                CompressionType::from_sys_flag(sys_flag::COMPRESSED | compression_type.sys_flag())
+                    .unwrap()
+            );
+            assert!(compression_type
+                .decompress(b"not compressed", DEFAULT_MAX_BODY_SIZE)
+                .is_err());
+        }
+        assert_eq!(
+            CompressionType::Zlib,
+            CompressionType::from_sys_flag(sys_flag::COMPRESSED).unwrap()
+        );
+        assert!(CompressionType::from_sys_flag(0x7 << 8).is_err());
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        // Compresses to a few kilobytes at most.
+        let data = vec![0; DEFAULT_MAX_BODY_SIZE + 1];
+        for compression_type in TYPES {
+            let compressed = compression_type.compress(&data, 5).unwrap();
+            assert!(matches!(
+                compression_type.decompress(&compressed, DEFAULT_MAX_BODY_SIZE),
+                Err(Error::DecodeCommandError(_))
+            ));
+            assert_eq!(
+                data.len(),
+                compression_type
+                    .decompress(&compressed, data.len())
+                    .unwrap()
+                    .len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_decompress_java_zlib() {
+        // A zlib stream at level 5, as `java.util.zip.Deflater` writes it for
+        // `UtilAll.compress("Hello RocketMQ".getBytes(), 5)`.
+        let compressed = [
+            0x78, 0x5e, 0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x08, 0xca, 0x4f, 0xce, 0x4e, 0x2d,
+            0xf1, 0x0d, 0x04, 0x00, 0x26, 0x26, 0x05, 0x1b,
+        ];
+        assert_eq!(
+            b"Hello RocketMQ".to_vec(),
+            CompressionType::Zlib
+                .decompress(&compressed, DEFAULT_MAX_BODY_SIZE)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compressor_threshold() {
+        let compressor = MessageCompressor {
+            compression_type: CompressionType::Zstd,
+            threshold: 16,
+            ..Default::default()
+        };
+        let mut header = SendMessageRequestHeader::default();
+        let body = compressor
+            .compress(&mut header, Bytes::from_static(b"short"))
+            .unwrap();
+        assert_eq!(&b"short"[..], &body[..]);
+        assert_eq!(0, header.sys_flag);
+
+        let long = Bytes::from("a".repeat(1024));
+        let body = compressor.compress(&mut header, long.clone()).unwrap();
+        assert!(body.len() < long.len());
+        assert_eq!(
+            sys_flag::COMPRESSED | sys_flag::COMPRESSION_ZSTD_TYPE,
+            header.sys_flag
+        );
+        assert_eq!(
+            long,
+            CompressionType::Zstd
+                .decompress(&body, DEFAULT_MAX_BODY_SIZE)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+use crate::util::Error;
+
+use super::command::Command;
+
+/// Largest frame accepted by default, like `com.rocketmq.remoting.frameMaxLength` in Java.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// A connection commands are framed on, plain TCP or TLS.
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+pub(crate) type BoxStream = Box<dyn Stream>;
+
+pub(crate) type CommandReader = FramedRead<ReadHalf<BoxStream>, CommandCodec>;
+
+/// Splits a stream into framed halves that read and write whole commands.
+pub(crate) fn split(stream: BoxStream, max_frame_size: usize) -> (CommandReader, CommandWriter) {
+    let (reader, writer) = tokio::io::split(stream);
+    (
+        FramedRead::new(reader, CommandCodec::new(max_frame_size)),
+        CommandWriter {
+            writer,
+            header_buf: BytesMut::new(),
+            max_frame_size,
+        },
+    )
+}
+
+/// Whether a failed write left the stream unusable. Oversized commands are rejected
+/// before anything is written, so the stream stays intact.
+pub(crate) fn is_broken<T>(result: &Result<T, Error>) -> bool {
+    matches!(result, Err(e) if !matches!(e, Error::FrameTooLarge { .. }))
+}
+
+/// Writes commands with the header and body as separate buffers, so bodies are never
+/// copied, and written with a single vectored write where the stream supports it.
+pub(crate) struct CommandWriter {
+    writer: WriteHalf<BoxStream>,
+    header_buf: BytesMut,
+    max_frame_size: usize,
+}
+
+impl CommandWriter {
+    /// Writes `command`, returning the size of its frame.
+    pub async fn send(&mut self, command: Command) -> Result<usize, Error> {
+        self.header_buf.clear();
+        command.encode_header(&mut self.header_buf);
+        let length = (&self.header_buf[..4]).get_u32() as usize;
+        // Rejected here rather than by the peer, which would close the connection.
+        if length > self.max_frame_size {
+            return Err(Error::FrameTooLarge {
+                length,
+                max: self.max_frame_size,
+            });
+        }
+        let body = command.body().cloned().unwrap_or_default();
+        let mut frame = (&self.header_buf[..]).chain(body);
+        self.writer.write_all_buf(&mut frame).await?;
+        Ok(4 + length)
+    }
+
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        Ok(self.writer.shutdown().await?)
+    }
+}
+
+/// Length-delimited frames: a 4 byte big-endian length, then as many bytes of header
+/// and body. The length counts neither itself nor may it exceed `max_frame_size`.
+///
+/// Decoded bodies share the memory of the read buffer instead of being copied out.
+#[derive(Debug, Clone)]
+pub struct CommandCodec {
+    max_frame_size: usize,
+    bytes_read: usize,
+}
+
+impl CommandCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            bytes_read: 0,
+        }
+    }
+
+    /// Size of the frames decoded since the last call.
+    pub(crate) fn take_bytes_read(&mut self) -> usize {
+        std::mem::take(&mut self.bytes_read)
+    }
+}
+
+impl Default for CommandCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl Decoder for CommandCodec {
+    type Item = Command;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Command>, Error> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let length = (&src[..4]).get_u32() as usize;
+        if length > self.max_frame_size {
+            return Err(Error::FrameTooLarge {
+                length,
+                max: self.max_frame_size,
+            });
+        }
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(4 + length).freeze();
+        self.bytes_read += frame.len();
+        Command::decode_bytes(frame).map(Some)
+    }
+}
+
+impl Encoder<Command> for CommandCodec {
+    type Error = Error;
+
+    fn encode(&mut self, command: Command, dst: &mut BytesMut) -> Result<(), Error> {
+        let start = dst.len();
+        command.encode_header(dst);
+        let length = (&dst[start..start + 4]).get_u32() as usize;
+        if length > self.max_frame_size {
+            dst.truncate(start);
+            return Err(Error::FrameTooLarge {
+                length,
+                max: self.max_frame_size,
+            });
+        }
+        if let Some(body) = command.body() {
+            dst.extend_from_slice(body);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::common::command::SerializeType;
+
+    fn codec() -> CommandCodec {
+        CommandCodec::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Commands along with the properties they were given.
+    fn command_strategy() -> impl Strategy<Value = (Command, HashMap<String, String>)> {
+        (
+            // The binary header stores the code in 2 bytes, like Java.
+            any::<i16>().prop_map(i32::from),
+            any::<bool>(),
+            // An empty remark is written like a missing one.
+            proptest::option::of(".{1,32}"),
+            proptest::collection::hash_map("[a-zA-Z]{1,8}", ".{0,16}", 0..4),
+            proptest::option::of(proptest::collection::vec(any::<u8>(), 1..256)),
+        )
+            .prop_map(|(code, rocketmq, remark, ext_fields, body)| {
+                let mut command = Command::new(code);
+                if rocketmq {
+                    command.set_serialize_type(SerializeType::Rocketmq);
+                }
+                if let Some(remark) = remark {
+                    command.set_remark(remark);
+                }
+                for (key, value) in &ext_fields {
+                    command.add_property(key, value);
+                }
+                if let Some(body) = body {
+                    command.set_body(body);
+                }
+                (command, ext_fields)
+            })
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        let mut codec = CommandCodec::new(16);
+        let mut src = BytesMut::from(&[0x00, 0x00, 0x00, 0x11][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(Error::FrameTooLarge {
+                length: 17,
+                max: 16
+            })
+        ));
+
+        let mut dst = BytesMut::new();
+        assert!(matches!(
+            codec.encode(Command::new(0), &mut dst),
+            Err(Error::FrameTooLarge { max: 16, .. })
+        ));
+        assert!(dst.is_empty());
+    }
+
+    #[test]
+    fn test_decode_shares_body() {
+        let mut command = Command::new(0);
+        command.set_body(vec![7; 1024]);
+        let mut src = BytesMut::new();
+        codec().encode(command, &mut src).unwrap();
+        let buffer = src.as_ptr() as usize..src.as_ptr() as usize + src.len();
+
+        let decoded = codec().decode(&mut src).unwrap().unwrap();
+        assert!(buffer.contains(&(decoded.body().unwrap().as_ptr() as usize)));
+    }
+
+    #[test]
+    fn test_truncated_header_length() {
+        // Claims a 255 byte header in a 4 byte frame.
+        let mut src = BytesMut::from(&[0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xff][..]);
+        assert!(matches!(
+            codec().decode(&mut src),
+            Err(Error::DecodeCommandError(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_decode_arbitrary_bytes(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = Command::decode(&data);
+            let mut src = BytesMut::from(&data[..]);
+            while let Ok(Some(_)) = codec().decode(&mut src) {}
+        }
+
+        #[test]
+        fn prop_round_trip(
+            commands in proptest::collection::vec(command_strategy(), 1..8),
+            chunk_size in 1usize..64,
+        ) {
+            let mut encoded = BytesMut::new();
+            for (command, _) in &commands {
+                codec().encode(command.clone(), &mut encoded).unwrap();
+            }
+
+            // Fed in chunks, as a socket might deliver them.
+            let mut src = BytesMut::new();
+            let mut decoded = Vec::new();
+            for chunk in encoded.chunks(chunk_size) {
+                src.extend_from_slice(chunk);
+                while let Some(command) = codec().decode(&mut src).unwrap() {
+                    decoded.push(command);
+                }
+            }
+            prop_assert!(src.is_empty());
+            prop_assert_eq!(commands.len(), decoded.len());
+            for ((command, ext_fields), decoded) in commands.iter().zip(&decoded) {
+                prop_assert_eq!(command.code(), decoded.code());
+                prop_assert_eq!(command.opaque(), decoded.opaque());
+                prop_assert_eq!(command.serialize_type(), decoded.serialize_type());
+                prop_assert_eq!(command.remark(), decoded.remark());
+                prop_assert_eq!(command.body(), decoded.body());
+                for (key, value) in ext_fields {
+                    prop_assert_eq!(Some(value), decoded.get_property(key));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Headers of the requests served by brokers.
+
+use super::custom_header;
+
+custom_header! {
+    pub struct SendMessageRequestHeader {
+        "producerGroup" => producer_group: String,
+        "topic" => topic: String,
+        "defaultTopic" => default_topic: String,
+        "defaultTopicQueueNums" => default_topic_queue_nums: i32,
+        "queueId" => queue_id: i32,
+        "sysFlag" => sys_flag: i32,
+        "bornTimestamp" => born_timestamp: i64,
+        "flag" => flag: i32,
+        "properties" => properties: Option<String>,
+        "reconsumeTimes" => reconsume_times: Option<i32>,
+        "unitMode" => unit_mode: Option<bool>,
+        "batch" => batch: Option<bool>,
+        "maxReconsumeTimes" => max_reconsume_times: Option<i32>,
+    }
+
+    /// [`SendMessageRequestHeader`] with single letter keys, sent with `SEND_MESSAGE_V2`
+    /// and `SEND_BATCH_MESSAGE` to save space on every message.
+    pub struct SendMessageRequestHeaderV2 {
+        "a" => a: String,
+        "b" => b: String,
+        "c" => c: String,
+        "d" => d: i32,
+        "e" => e: i32,
+        "f" => f: i32,
+        "g" => g: i64,
+        "h" => h: i32,
+        "i" => i: Option<String>,
+        "j" => j: Option<i32>,
+        "k" => k: Option<bool>,
+        "l" => l: Option<i32>,
+        "m" => m: Option<bool>,
+    }
+
+    pub struct SendMessageResponseHeader {
+        "msgId" => msg_id: String,
+        "queueId" => queue_id: i32,
+        "queueOffset" => queue_offset: i64,
+        "transactionId" => transaction_id: Option<String>,
+        "batchUniqId" => batch_uniq_id: Option<String>,
+    }
+
+    pub struct PullMessageRequestHeader {
+        "consumerGroup" => consumer_group: String,
+        "topic" => topic: String,
+        "queueId" => queue_id: i32,
+        "queueOffset" => queue_offset: i64,
+        "maxMsgNums" => max_msg_nums: i32,
+        "sysFlag" => sys_flag: i32,
+        "commitOffset" => commit_offset: i64,
+        "suspendTimeoutMillis" => suspend_timeout_millis: i64,
+        "subscription" => subscription: Option<String>,
+        "subVersion" => sub_version: i64,
+        "expressionType" => expression_type: Option<String>,
+        "maxMsgBytes" => max_msg_bytes: Option<i32>,
+    }
+
+    pub struct PullMessageResponseHeader {
+        "suggestWhichBrokerId" => suggest_which_broker_id: i64,
+        "nextBeginOffset" => next_begin_offset: i64,
+        "minOffset" => min_offset: i64,
+        "maxOffset" => max_offset: i64,
+        "offsetDelta" => offset_delta: Option<i64>,
+        "topicSysFlag" => topic_sys_flag: Option<i32>,
+        "groupSysFlag" => group_sys_flag: Option<i32>,
+        "forbiddenType" => forbidden_type: Option<i32>,
+    }
+
+    pub struct QueryConsumerOffsetRequestHeader {
+        "consumerGroup" => consumer_group: String,
+        "topic" => topic: String,
+        "queueId" => queue_id: i32,
+        "setZeroIfNotFound" => set_zero_if_not_found: Option<bool>,
+    }
+
+    pub struct QueryConsumerOffsetResponseHeader {
+        "offset" => offset: i64,
+    }
+
+    pub struct UpdateConsumerOffsetRequestHeader {
+        "consumerGroup" => consumer_group: String,
+        "topic" => topic: String,
+        "queueId" => queue_id: i32,
+        "commitOffset" => commit_offset: i64,
+    }
+
+    pub struct GetMaxOffsetRequestHeader {
+        "topic" => topic: String,
+        "queueId" => queue_id: i32,
+    }
+
+    pub struct GetMaxOffsetResponseHeader {
+        "offset" => offset: i64,
+    }
+
+    pub struct GetMinOffsetRequestHeader {
+        "topic" => topic: String,
+        "queueId" => queue_id: i32,
+    }
+
+    pub struct GetMinOffsetResponseHeader {
+        "offset" => offset: i64,
+    }
+
+    pub struct SearchOffsetRequestHeader {
+        "topic" => topic: String,
+        "queueId" => queue_id: i32,
+        "timestamp" => timestamp: i64,
+    }
+
+    pub struct SearchOffsetResponseHeader {
+        "offset" => offset: i64,
+    }
+
+    pub struct ViewMessageRequestHeader {
+        "offset" => offset: i64,
+    }
+
+    pub struct EndTransactionRequestHeader {
+        "topic" => topic: Option<String>,
+        "producerGroup" => producer_group: String,
+        "tranStateTableOffset" => tran_state_table_offset: i64,
+        "commitLogOffset" => commit_log_offset: i64,
+        "commitOrRollback" => commit_or_rollback: i32,
+        "fromTransactionCheck" => from_transaction_check: Option<bool>,
+        "msgId" => msg_id: String,
+        "transactionId" => transaction_id: Option<String>,
+    }
+
+    pub struct UnregisterClientRequestHeader {
+        "clientID" => client_id: String,
+        "producerGroup" => producer_group: Option<String>,
+        "consumerGroup" => consumer_group: Option<String>,
+    }
+}
+
+impl From<&SendMessageRequestHeader> for SendMessageRequestHeaderV2 {
+    fn from(v1: &SendMessageRequestHeader) -> Self {
+        Self {
+            a: v1.producer_group.clone(),
+            b: v1.topic.clone(),
+            c: v1.default_topic.clone(),
+            d: v1.default_topic_queue_nums,
+            e: v1.queue_id,
+            f: v1.sys_flag,
+            g: v1.born_timestamp,
+            h: v1.flag,
+            i: v1.properties.clone(),
+            j: v1.reconsume_times,
+            k: v1.unit_mode,
+            l: v1.max_reconsume_times,
+            m: v1.batch,
+        }
+    }
+}
+
+impl From<SendMessageRequestHeaderV2> for SendMessageRequestHeader {
+    fn from(v2: SendMessageRequestHeaderV2) -> Self {
+        Self {
+            producer_group: v2.a,
+            topic: v2.b,
+            default_topic: v2.c,
+            default_topic_queue_nums: v2.d,
+            queue_id: v2.e,
+            sys_flag: v2.f,
+            born_timestamp: v2.g,
+            flag: v2.h,
+            properties: v2.i,
+            reconsume_times: v2.j,
+            unit_mode: v2.k,
+            batch: v2.m,
+            max_reconsume_times: v2.l,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::common::header::CommandCustomHeader;
+
+    #[test]
+    fn test_pull_message_request_header() {
+        let ext_fields: HashMap<String, String> = [
+            ("consumerGroup", "please_rename_unique_group_name"),
+            ("topic", "TopicTest"),
+            ("queueId", "2"),
+            ("queueOffset", "128"),
+            ("maxMsgNums", "32"),
+            ("sysFlag", "3"),
+            ("commitOffset", "0"),
+            ("suspendTimeoutMillis", "15000"),
+            ("subscription", "*"),
+            ("subVersion", "1722500000000"),
+            ("expressionType", "TAG"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let header = PullMessageRequestHeader::decode(&ext_fields).unwrap();
+        assert_eq!("TopicTest", header.topic);
+        assert_eq!(2, header.queue_id);
+        assert_eq!(128, header.queue_offset);
+        assert_eq!(Some("*".to_string()), header.subscription);
+        assert_eq!(None, header.max_msg_bytes);
+
+        let mut encoded = HashMap::new();
+        header.encode(&mut encoded);
+        assert_eq!(ext_fields, encoded);
+    }
+
+    #[test]
+    fn test_send_message_request_header_v2() {
+        let header = SendMessageRequestHeader {
+            producer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            default_topic: "TBW102".to_string(),
+            default_topic_queue_nums: 4,
+            properties: Some("KEYS\u{1}a\u{2}".to_string()),
+            max_reconsume_times: Some(16),
+            ..Default::default()
+        };
+        let v2 = SendMessageRequestHeaderV2::from(&header);
+        let mut ext_fields = HashMap::new();
+        v2.encode(&mut ext_fields);
+        assert_eq!("TopicTest", ext_fields["b"]);
+        assert_eq!("16", ext_fields["l"]);
+        assert!(!ext_fields.contains_key("topic"));
+        assert_eq!(
+            header,
+            SendMessageRequestHeaderV2::decode(&ext_fields)
+                .unwrap()
+                .into()
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Headers of the requests brokers send to clients.
+
+use super::custom_header;
+
+custom_header! {
+    pub struct CheckTransactionStateRequestHeader {
+        "topic" => topic: Option<String>,
+        "tranStateTableOffset" => tran_state_table_offset: i64,
+        "commitLogOffset" => commit_log_offset: i64,
+        "msgId" => msg_id: Option<String>,
+        "transactionId" => transaction_id: Option<String>,
+        "offsetMsgId" => offset_msg_id: Option<String>,
+    }
+
+    pub struct NotifyConsumerIdsChangedRequestHeader {
+        "consumerGroup" => consumer_group: String,
+    }
+
+    pub struct ResetOffsetRequestHeader {
+        "topic" => topic: String,
+        "group" => group: String,
+        "queueId" => queue_id: Option<i32>,
+        "offset" => offset: Option<i64>,
+        "timestamp" => timestamp: i64,
+        "isForce" => is_force: bool,
+    }
+
+    pub struct GetConsumerRunningInfoRequestHeader {
+        "consumerGroup" => consumer_group: String,
+        "clientId" => client_id: String,
+        "jstackEnable" => jstack_enable: Option<bool>,
+    }
+
+    pub struct GetConsumerStatusRequestHeader {
+        "topic" => topic: String,
+        "group" => group: String,
+        "clientAddr" => client_addr: Option<String>,
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use crate::util::Error;
+
+pub mod broker;
+pub mod client;
+pub mod namesrv;
+
+/// A typed view of the `ext_fields` of a command, like `CommandCustomHeader` in Java.
+pub trait CommandCustomHeader: Sized {
+    /// Writes every present field into `ext_fields`. Absent optional fields are skipped.
+    fn encode(&self, ext_fields: &mut HashMap<String, String>);
+
+    /// Reads the header back, failing on missing required fields or unparsable values.
+    fn decode(ext_fields: &HashMap<String, String>) -> Result<Self, Error>;
+}
+
+/// A value that travels as a string in `ext_fields`.
+pub trait HeaderValue: Sized {
+    fn to_header_value(&self) -> String;
+
+    fn from_header_value(value: &str) -> Option<Self>;
+}
+
+macro_rules! parsed_header_value {
+    ($($ty:ty),*) => {
+        $(
+            impl HeaderValue for $ty {
+                fn to_header_value(&self) -> String {
+                    self.to_string()
+                }
+
+                fn from_header_value(value: &str) -> Option<Self> {
+                    value.trim().parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+parsed_header_value!(i32, i64, u32, u64);
+
+impl HeaderValue for String {
+    fn to_header_value(&self) -> String {
+        self.clone()
+    }
+
+    fn from_header_value(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+impl HeaderValue for bool {
+    fn to_header_value(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_header_value(value: &str) -> Option<Self> {
+        Some(value.trim().eq_ignore_ascii_case("true"))
+    }
+}
+
+/// A header field: a required [`HeaderValue`] or an optional one wrapped in `Option`.
+pub trait HeaderField: Sized {
+    fn put(&self, name: &'static str, ext_fields: &mut HashMap<String, String>);
+
+    fn take(name: &'static str, ext_fields: &HashMap<String, String>) -> Result<Self, Error>;
+}
+
+impl<T: HeaderValue> HeaderField for T {
+    fn put(&self, name: &'static str, ext_fields: &mut HashMap<String, String>) {
+        ext_fields.insert(name.to_string(), self.to_header_value());
+    }
+
+    fn take(name: &'static str, ext_fields: &HashMap<String, String>) -> Result<Self, Error> {
+        let value = ext_fields
+            .get(name)
+            .ok_or(Error::MissingHeaderField(name))?;
+        T::from_header_value(value).ok_or_else(|| Error::InvalidHeaderField {
+            name,
+            value: value.clone(),
+        })
+    }
+}
+
+impl<T: HeaderValue> HeaderField for Option<T> {
+    fn put(&self, name: &'static str, ext_fields: &mut HashMap<String, String>) {
+        if let Some(value) = self {
+            value.put(name, ext_fields);
+        }
+    }
+
+    fn take(name: &'static str, ext_fields: &HashMap<String, String>) -> Result<Self, Error> {
+        if ext_fields.contains_key(name) {
+            T::take(name, ext_fields).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Declares a header struct whose fields map onto the given `ext_fields` keys.
+macro_rules! custom_header {
+    ($(
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $($key:literal => $field:ident: $ty:ty,)*
+        }
+    )*) => {$(
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, PartialEq)]
+        pub struct $name {
+            $(pub $field: $ty,)*
+        }
+
+        impl $crate::common::header::CommandCustomHeader for $name {
+            fn encode(&self, ext_fields: &mut std::collections::HashMap<String, String>) {
+                $($crate::common::header::HeaderField::put(&self.$field, $key, ext_fields);)*
+            }
+
+            fn decode(
+                ext_fields: &std::collections::HashMap<String, String>,
+            ) -> Result<Self, $crate::util::Error> {
+                Ok(Self {
+                    $($field: $crate::common::header::HeaderField::take($key, ext_fields)?,)*
+                })
+            }
+        }
+    )*};
+}
+
+pub(crate) use custom_header;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    custom_header! {
+        pub struct TestHeader {
+            "topic" => topic: String,
+            "queueId" => queue_id: i32,
+            "unitMode" => unit_mode: Option<bool>,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let header = TestHeader {
+            topic: "TopicTest".to_string(),
+            queue_id: 3,
+            unit_mode: None,
+        };
+        let mut ext_fields = HashMap::new();
+        header.encode(&mut ext_fields);
+        assert_eq!("3", ext_fields.get("queueId").unwrap());
+        assert!(!ext_fields.contains_key("unitMode"));
+        assert_eq!(header, TestHeader::decode(&ext_fields).unwrap());
+    }
+
+    #[test]
+    fn test_decode_missing_field() {
+        let mut ext_fields = HashMap::new();
+        ext_fields.insert("topic".to_string(), "TopicTest".to_string());
+        assert!(matches!(
+            TestHeader::decode(&ext_fields),
+            Err(Error::MissingHeaderField("queueId"))
+        ));
+    }
+
+    #[test]
+    fn test_decode_invalid_field() {
+        let mut ext_fields = HashMap::new();
+        ext_fields.insert("topic".to_string(), "TopicTest".to_string());
+        ext_fields.insert("queueId".to_string(), "one".to_string());
+        assert!(matches!(
+            TestHeader::decode(&ext_fields),
+            Err(Error::InvalidHeaderField {
+                name: "queueId",
+                ..
+            })
+        ));
+
+        ext_fields.insert("queueId".to_string(), "1".to_string());
+        ext_fields.insert("unitMode".to_string(), "TRUE".to_string());
+        assert_eq!(
+            Some(true),
+            TestHeader::decode(&ext_fields).unwrap().unit_mode
+        );
+    }
+}
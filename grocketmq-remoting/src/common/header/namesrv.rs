@@ -0,0 +1,34 @@
+//! Headers of the requests served by name servers.
+
+use super::custom_header;
+
+custom_header! {
+    pub struct GetRouteInfoRequestHeader {
+        "topic" => topic: String,
+        "acceptStandardJsonOnly" => accept_standard_json_only: Option<bool>,
+    }
+
+    pub struct RegisterBrokerRequestHeader {
+        "brokerName" => broker_name: String,
+        "brokerAddr" => broker_addr: String,
+        "clusterName" => cluster_name: String,
+        "haServerAddr" => ha_server_addr: String,
+        "brokerId" => broker_id: i64,
+        "heartbeatTimeoutMillis" => heartbeat_timeout_millis: Option<i64>,
+        "enableActingMaster" => enable_acting_master: Option<bool>,
+        "compressed" => compressed: bool,
+        "bodyCrc32" => body_crc32: i32,
+    }
+
+    pub struct RegisterBrokerResponseHeader {
+        "haServerAddr" => ha_server_addr: Option<String>,
+        "masterAddr" => master_addr: Option<String>,
+    }
+
+    pub struct UnRegisterBrokerRequestHeader {
+        "brokerName" => broker_name: String,
+        "brokerAddr" => broker_addr: String,
+        "clusterName" => cluster_name: String,
+        "brokerId" => broker_id: i64,
+    }
+}
@@ -0,0 +1,621 @@
+//! Messages, and the binary layout brokers store them in and return them with from pulls,
+//! `MessageDecoder` in Java.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::util::Error;
+
+use super::{
+    compression::{CompressionType, DEFAULT_MAX_BODY_SIZE},
+    message_id::MessageId,
+};
+
+/// Magic code of messages whose topic length is stored in one byte.
+pub const MESSAGE_MAGIC_CODE_V1: i32 = 0xdaa320a7_u32 as i32;
+/// Magic code of messages whose topic length is stored in two bytes.
+pub const MESSAGE_MAGIC_CODE_V2: i32 = 0xdaa320ab_u32 as i32;
+
+const NAME_VALUE_SEPARATOR: char = '\u{1}';
+const PROPERTY_SEPARATOR: char = '\u{2}';
+
+/// Bits of `sysFlag`, `MessageSysFlag` in Java.
+pub mod sys_flag {
+    pub const COMPRESSED: i32 = 0x1;
+    pub const MULTI_TAGS: i32 = 0x1 << 1;
+    pub const TRANSACTION_NOT_TYPE: i32 = 0;
+    pub const TRANSACTION_PREPARED_TYPE: i32 = 0x1 << 2;
+    pub const TRANSACTION_COMMIT_TYPE: i32 = 0x2 << 2;
+    pub const TRANSACTION_ROLLBACK_TYPE: i32 = 0x3 << 2;
+    pub const BORNHOST_V6: i32 = 0x1 << 4;
+    pub const STOREHOST_V6: i32 = 0x1 << 5;
+    pub const NEED_UNWRAP: i32 = 0x1 << 6;
+    pub const INNER_BATCH: i32 = 0x1 << 7;
+    pub const COMPRESSION_LZ4_TYPE: i32 = 0x1 << 8;
+    pub const COMPRESSION_ZSTD_TYPE: i32 = 0x2 << 8;
+    pub const COMPRESSION_ZLIB_TYPE: i32 = 0x3 << 8;
+    pub const COMPRESSION_TYPE_COMPARATOR: i32 = 0x7 << 8;
+}
+
+/// Keys of the properties the clients and brokers set, `MessageConst` in Java.
+pub mod property {
+    pub const KEYS: &str = "KEYS";
+    pub const TAGS: &str = "TAGS";
+    pub const WAIT_STORE_MSG_OK: &str = "WAIT";
+    pub const DELAY_TIME_LEVEL: &str = "DELAY";
+    pub const RETRY_TOPIC: &str = "RETRY_TOPIC";
+    pub const REAL_TOPIC: &str = "REAL_TOPIC";
+    pub const REAL_QUEUE_ID: &str = "REAL_QID";
+    pub const TRANSACTION_PREPARED: &str = "TRAN_MSG";
+    pub const PRODUCER_GROUP: &str = "PGROUP";
+    pub const MIN_OFFSET: &str = "MIN_OFFSET";
+    pub const MAX_OFFSET: &str = "MAX_OFFSET";
+    pub const UNIQ_CLIENT_MESSAGE_ID_KEYIDX: &str = "UNIQ_KEY";
+    pub const RECONSUME_TIME: &str = "RECONSUME_TIME";
+    pub const MAX_RECONSUME_TIMES: &str = "MAX_RECONSUME_TIMES";
+    pub const SHARDING_KEY: &str = "__SHARDINGKEY";
+}
+
+/// A message as producers send it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub flag: i32,
+    pub properties: HashMap<String, String>,
+    pub body: Bytes,
+}
+
+impl Message {
+    pub fn new(topic: impl Into<String>, body: impl Into<Bytes>) -> Self {
+        Self {
+            topic: topic.into(),
+            body: body.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    pub fn put_property(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.properties.insert(key.into(), value.into());
+    }
+
+    pub fn tags(&self) -> Option<&str> {
+        self.property(property::TAGS)
+    }
+
+    pub fn set_tags(&mut self, tags: impl Into<String>) {
+        self.put_property(property::TAGS, tags);
+    }
+
+    /// The keys of the message, which are stored separated by spaces.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.property(property::KEYS)
+            .unwrap_or_default()
+            .split(' ')
+            .filter(|key| !key.is_empty())
+    }
+
+    pub fn set_keys<'a>(&mut self, keys: impl IntoIterator<Item = &'a str>) {
+        self.put_property(
+            property::KEYS,
+            keys.into_iter().collect::<Vec<_>>().join(" "),
+        );
+    }
+}
+
+/// A message as a broker stored it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageExt {
+    pub message: Message,
+    pub queue_id: i32,
+    pub store_size: i32,
+    pub queue_offset: i64,
+    pub sys_flag: i32,
+    pub born_timestamp: i64,
+    pub born_host: SocketAddr,
+    pub store_timestamp: i64,
+    pub store_host: SocketAddr,
+    pub body_crc: i32,
+    pub reconsume_times: i32,
+    pub commit_log_offset: i64,
+    pub prepared_transaction_offset: i64,
+}
+
+impl Default for MessageExt {
+    fn default() -> Self {
+        let unspecified = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+        Self {
+            message: Message::default(),
+            queue_id: 0,
+            store_size: 0,
+            queue_offset: 0,
+            sys_flag: 0,
+            born_timestamp: 0,
+            born_host: unspecified,
+            store_timestamp: 0,
+            store_host: unspecified,
+            body_crc: 0,
+            reconsume_times: 0,
+            commit_log_offset: 0,
+            prepared_transaction_offset: 0,
+        }
+    }
+}
+
+impl MessageExt {
+    /// The id of the message: the unique id its producer gave it, or the offset id for
+    /// messages sent without one.
+    pub fn msg_id(&self) -> String {
+        match self
+            .message
+            .property(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+        {
+            Some(id) => id.to_string(),
+            None => self.offset_msg_id(),
+        }
+    }
+
+    /// The id the broker derives from its address and where it stored the message.
+    pub fn offset_msg_id(&self) -> String {
+        MessageId::new(self.store_host, self.commit_log_offset).encode()
+    }
+}
+
+/// Joins properties into the string that is stored with messages and sent in the
+/// `properties` header: `key\u0001value\u0002` for each.
+pub fn properties_to_string(properties: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    for (key, value) in properties {
+        result.push_str(key);
+        result.push(NAME_VALUE_SEPARATOR);
+        result.push_str(value);
+        result.push(PROPERTY_SEPARATOR);
+    }
+    result
+}
+
+/// Splits a string written by [`properties_to_string`]. Properties without a value are
+/// dropped, like in Java.
+pub fn string_to_properties(properties: &str) -> HashMap<String, String> {
+    properties
+        .split(PROPERTY_SEPARATOR)
+        .filter_map(|property| property.split_once(NAME_VALUE_SEPARATOR))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Decodes one stored message from the front of `buf`, advancing past it. Compressed
+/// bodies are decompressed as `sys_flag` says, up to [`DEFAULT_MAX_BODY_SIZE`], and the
+/// compression bits are cleared from it so the message encodes as it was decoded; other
+/// bodies share the memory of `buf`.
+pub fn decode(buf: &mut Bytes) -> Result<MessageExt, Error> {
+    ensure(buf, 4, "total size")?;
+    let store_size = (&buf[..4]).get_i32();
+    if store_size < 4 {
+        return Err(bad_message(format!("invalid total size {}", store_size)));
+    }
+    ensure(buf, store_size as usize, "message")?;
+    let mut buf = buf.split_to(store_size as usize);
+    buf.advance(4);
+
+    ensure(&buf, 4 * 4 + 8 * 2 + 4 + 8, "fixed fields")?;
+    let magic_code = buf.get_i32();
+    if magic_code != MESSAGE_MAGIC_CODE_V1 && magic_code != MESSAGE_MAGIC_CODE_V2 {
+        return Err(bad_message(format!("unknown magic code {:#x}", magic_code)));
+    }
+    let body_crc = buf.get_i32();
+    let queue_id = buf.get_i32();
+    let flag = buf.get_i32();
+    let queue_offset = buf.get_i64();
+    let commit_log_offset = buf.get_i64();
+    let mut sys_flag = buf.get_i32();
+    let born_timestamp = buf.get_i64();
+    let born_host = decode_host(&mut buf, sys_flag & sys_flag::BORNHOST_V6 != 0, "born host")?;
+    ensure(&buf, 8, "store timestamp")?;
+    let store_timestamp = buf.get_i64();
+    let store_host = decode_host(
+        &mut buf,
+        sys_flag & sys_flag::STOREHOST_V6 != 0,
+        "store host",
+    )?;
+
+    ensure(&buf, 4 + 8 + 4, "reconsume times")?;
+    let reconsume_times = buf.get_i32();
+    let prepared_transaction_offset = buf.get_i64();
+    let body_length = buf.get_i32();
+    if body_length < 0 {
+        return Err(bad_message(format!("invalid body length {}", body_length)));
+    }
+    ensure(&buf, body_length as usize, "body")?;
+    let mut body = buf.split_to(body_length as usize);
+    if sys_flag & sys_flag::COMPRESSED != 0 {
+        body = CompressionType::from_sys_flag(sys_flag)?
+            .decompress(&body, DEFAULT_MAX_BODY_SIZE)?
+            .into();
+        sys_flag &= !(sys_flag::COMPRESSED | sys_flag::COMPRESSION_TYPE_COMPARATOR);
+    }
+
+    let topic_length = if magic_code == MESSAGE_MAGIC_CODE_V2 {
+        ensure(&buf, 2, "topic length")?;
+        buf.get_u16() as usize
+    } else {
+        ensure(&buf, 1, "topic length")?;
+        buf.get_u8() as usize
+    };
+    ensure(&buf, topic_length, "topic")?;
+    let topic = decode_string(&buf.split_to(topic_length), "topic")?;
+
+    ensure(&buf, 2, "properties length")?;
+    let properties_length = buf.get_u16() as usize;
+    ensure(&buf, properties_length, "properties")?;
+    let properties = string_to_properties(&decode_string(
+        &buf.split_to(properties_length),
+        "properties",
+    )?);
+
+    Ok(MessageExt {
+        message: Message {
+            topic,
+            flag,
+            properties,
+            body,
+        },
+        queue_id,
+        store_size,
+        queue_offset,
+        sys_flag,
+        born_timestamp,
+        born_host,
+        store_timestamp,
+        store_host,
+        body_crc,
+        reconsume_times,
+        commit_log_offset,
+        prepared_transaction_offset,
+    })
+}
+
+/// Decodes the messages stored back to back in the body of a pull response.
+pub fn decode_all(mut buf: Bytes) -> Result<Vec<MessageExt>, Error> {
+    let mut messages = Vec::new();
+    while buf.has_remaining() {
+        messages.push(decode(&mut buf)?);
+    }
+    Ok(messages)
+}
+
+/// Encodes a message in the layout brokers store it in. The magic code is
+/// [`MESSAGE_MAGIC_CODE_V2`] for topics too long for one length byte, and the host
+/// flags of `sys_flag` follow the address families of the hosts. The body is written as
+/// is, so it must already be compressed if `sys_flag` says so.
+///
+/// Java reads the lengths of the topic and properties as signed, so topics and
+/// properties over [`i16::MAX`] bytes fail with [`Error::MessageIllegal`].
+pub fn encode(message: &MessageExt) -> Result<Bytes, Error> {
+    let topic = message.message.topic.as_bytes();
+    check_length(topic.len(), "topic")?;
+    let properties = properties_to_string(&message.message.properties);
+    check_length(properties.len(), "properties")?;
+    let body = &message.message.body;
+    let mut sys_flag = message.sys_flag & !(sys_flag::BORNHOST_V6 | sys_flag::STOREHOST_V6);
+    if message.born_host.is_ipv6() {
+        sys_flag |= sys_flag::BORNHOST_V6;
+    }
+    if message.store_host.is_ipv6() {
+        sys_flag |= sys_flag::STOREHOST_V6;
+    }
+    let v2 = topic.len() > i8::MAX as usize;
+
+    let mut buf = BytesMut::new();
+    buf.put_i32(0);
+    buf.put_i32(if v2 {
+        MESSAGE_MAGIC_CODE_V2
+    } else {
+        MESSAGE_MAGIC_CODE_V1
+    });
+    buf.put_i32(message.body_crc);
+    buf.put_i32(message.queue_id);
+    buf.put_i32(message.message.flag);
+    buf.put_i64(message.queue_offset);
+    buf.put_i64(message.commit_log_offset);
+    buf.put_i32(sys_flag);
+    buf.put_i64(message.born_timestamp);
+    encode_host(&mut buf, message.born_host);
+    buf.put_i64(message.store_timestamp);
+    encode_host(&mut buf, message.store_host);
+    buf.put_i32(message.reconsume_times);
+    buf.put_i64(message.prepared_transaction_offset);
+    buf.put_i32(body.len() as i32);
+    buf.put_slice(body);
+    if v2 {
+        buf.put_u16(topic.len() as u16);
+    } else {
+        buf.put_u8(topic.len() as u8);
+    }
+    buf.put_slice(topic);
+    buf.put_u16(properties.len() as u16);
+    buf.put_slice(properties.as_bytes());
+
+    let store_size = buf.len() as i32;
+    (&mut buf[..4]).put_i32(store_size);
+    Ok(buf.freeze())
+}
+
+/// Encodes messages of one topic into the body of `SEND_BATCH_MESSAGE`. Only the flag,
+/// body and properties of each message are sent; the topic goes in the header.
+/// Properties over [`i16::MAX`] bytes fail with [`Error::MessageIllegal`].
+pub fn encode_batch(messages: &[Message]) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::new();
+    for message in messages {
+        let properties = properties_to_string(&message.properties);
+        check_length(properties.len(), "properties")?;
+        let store_size = 4 * 5 + message.body.len() + 2 + properties.len();
+        buf.reserve(store_size);
+        buf.put_i32(store_size as i32);
+        // Magic code and body CRC are left to the broker.
+        buf.put_i32(0);
+        buf.put_i32(0);
+        buf.put_i32(message.flag);
+        buf.put_i32(message.body.len() as i32);
+        buf.put_slice(&message.body);
+        buf.put_u16(properties.len() as u16);
+        buf.put_slice(properties.as_bytes());
+    }
+    Ok(buf.freeze())
+}
+
+/// Decodes the body of a batch written by [`encode_batch`], like a broker does. The
+/// messages carry no topic, which the request header names.
+pub fn decode_batch(mut buf: Bytes) -> Result<Vec<Message>, Error> {
+    let mut messages = Vec::new();
+    while buf.has_remaining() {
+        ensure(&buf, 4 * 5, "batch fixed fields")?;
+        let store_size = (&buf[..4]).get_i32();
+        if store_size < 4 * 5 {
+            return Err(bad_message(format!("invalid total size {}", store_size)));
+        }
+        ensure(&buf, store_size as usize, "batch message")?;
+        let mut message = buf.split_to(store_size as usize);
+        message.advance(4 * 3);
+        let flag = message.get_i32();
+        let body_length = message.get_i32();
+        if body_length < 0 {
+            return Err(bad_message(format!("invalid body length {}", body_length)));
+        }
+        ensure(&message, body_length as usize, "body")?;
+        let body = message.split_to(body_length as usize);
+        ensure(&message, 2, "properties length")?;
+        let properties_length = message.get_u16() as usize;
+        ensure(&message, properties_length, "properties")?;
+        let properties = string_to_properties(&decode_string(
+            &message.split_to(properties_length),
+            "properties",
+        )?);
+        messages.push(Message {
+            topic: String::new(),
+            flag,
+            properties,
+            body,
+        });
+    }
+    Ok(messages)
+}
+
+fn decode_host(buf: &mut Bytes, ipv6: bool, what: &str) -> Result<SocketAddr, Error> {
+    let ip: IpAddr = if ipv6 {
+        ensure(buf, 16 + 4, what)?;
+        Ipv6Addr::from(buf.get_u128()).into()
+    } else {
+        ensure(buf, 4 + 4, what)?;
+        Ipv4Addr::from(buf.get_u32()).into()
+    };
+    let port = buf.get_i32();
+    let port =
+        u16::try_from(port).map_err(|_| bad_message(format!("invalid {} port {}", what, port)))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn encode_host(buf: &mut BytesMut, host: SocketAddr) {
+    match host.ip() {
+        IpAddr::V4(ip) => buf.put_slice(&ip.octets()),
+        IpAddr::V6(ip) => buf.put_slice(&ip.octets()),
+    }
+    buf.put_i32(host.port() as i32);
+}
+
+fn decode_string(data: &[u8], what: &str) -> Result<String, Error> {
+    String::from_utf8(data.to_vec()).map_err(|_| bad_message(format!("{} is not utf-8", what)))
+}
+
+fn ensure(buf: &Bytes, length: usize, what: &str) -> Result<(), Error> {
+    if buf.remaining() < length {
+        return Err(bad_message(format!(
+            "truncated {}: need {} bytes, have {}",
+            what,
+            length,
+            buf.remaining()
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if `length` doesn't fit the signed short Java reads it into.
+fn check_length(length: usize, what: &str) -> Result<(), Error> {
+    if length > i16::MAX as usize {
+        return Err(Error::MessageIllegal(format!(
+            "{} of {} bytes exceeds the limit of {} bytes",
+            what,
+            length,
+            i16::MAX
+        )));
+    }
+    Ok(())
+}
+
+fn bad_message(reason: String) -> Error {
+    Error::DecodeCommandError(format!("bad message: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_ext(topic: &str, body: &[u8]) -> MessageExt {
+        let mut message = Message::new(topic, body.to_vec());
+        message.set_tags("TagA");
+        message.set_keys(["order-1", "user-2"]);
+        MessageExt {
+            message,
+            queue_id: 3,
+            queue_offset: 128,
+            born_timestamp: 1_722_500_000_000,
+            born_host: "10.0.0.2:53122".parse().unwrap(),
+            store_timestamp: 1_722_500_000_007,
+            store_host: "[fe80::1]:10911".parse().unwrap(),
+            commit_log_offset: 4096,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_properties_string() {
+        let properties = string_to_properties("TAGS\u{1}TagA\u{2}KEYS\u{1}k1 k2\u{2}EMPTY\u{2}");
+        assert_eq!(2, properties.len());
+        assert_eq!("k1 k2", properties["KEYS"]);
+        assert_eq!(
+            properties,
+            string_to_properties(&properties_to_string(&properties))
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut expected = message_ext("TopicTest", b"Hello RocketMQ");
+        let mut buf = encode(&expected).unwrap();
+        assert_eq!(MESSAGE_MAGIC_CODE_V1, (&buf[4..8]).get_i32());
+
+        let decoded = decode(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        expected.store_size = decoded.store_size;
+        expected.sys_flag = sys_flag::STOREHOST_V6;
+        assert_eq!(expected, decoded);
+        assert_eq!(Some("TagA"), decoded.message.tags());
+        assert_eq!(
+            vec!["order-1", "user-2"],
+            decoded.message.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_decode_v2_magic_code() {
+        // Java reads the one byte topic length as signed.
+        for topic in ["t".repeat(128), "t".repeat(300)] {
+            let mut buf = encode(&message_ext(&topic, b"body")).unwrap();
+            assert_eq!(MESSAGE_MAGIC_CODE_V2, (&buf[4..8]).get_i32());
+            assert_eq!(topic, decode(&mut buf).unwrap().message.topic);
+        }
+    }
+
+    #[test]
+    fn test_encode_too_long() {
+        let topic = "t".repeat(i16::MAX as usize + 1);
+        assert!(matches!(
+            encode(&message_ext(&topic, b"body")),
+            Err(Error::MessageIllegal(_))
+        ));
+
+        let mut message = message_ext("TopicTest", b"body");
+        message
+            .message
+            .put_property(property::KEYS, "k".repeat(i16::MAX as usize));
+        assert!(matches!(encode(&message), Err(Error::MessageIllegal(_))));
+        assert!(matches!(
+            encode_batch(&[message.message]),
+            Err(Error::MessageIllegal(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_all() {
+        let mut buf = BytesMut::new();
+        for offset in 0..3 {
+            let mut message = message_ext("TopicTest", format!("body-{}", offset).as_bytes());
+            message.queue_offset = offset;
+            buf.extend_from_slice(&encode(&message).unwrap());
+        }
+        let messages = decode_all(buf.freeze()).unwrap();
+        assert_eq!(
+            vec![0, 1, 2],
+            messages.iter().map(|m| m.queue_offset).collect::<Vec<_>>()
+        );
+        assert_eq!(&b"body-2"[..], &messages[2].message.body[..]);
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let buf = encode(&message_ext("TopicTest", b"Hello RocketMQ")).unwrap();
+        for length in 0..buf.len() {
+            let mut truncated = buf.slice(..length);
+            assert!(matches!(
+                decode(&mut truncated),
+                Err(Error::DecodeCommandError(_))
+            ));
+        }
+
+        let mut bad_magic = BytesMut::from(&buf[..]);
+        (&mut bad_magic[4..8]).put_i32(0);
+        assert!(decode(&mut bad_magic.freeze()).is_err());
+    }
+
+    #[test]
+    fn test_encode_batch() {
+        let mut first = Message::new("TopicTest", b"first".to_vec());
+        first.set_tags("TagA");
+        let second = Message::new("TopicTest", b"second".to_vec());
+        let mut buf = encode_batch(&[first, second]).unwrap();
+
+        let decoded = decode_batch(buf.clone()).unwrap();
+        assert_eq!(2, decoded.len());
+        assert_eq!(Some("TagA"), decoded[0].tags());
+        assert_eq!(&b"second"[..], &decoded[1].body[..]);
+        assert!(decode_batch(buf.slice(..10)).is_err());
+
+        let store_size = buf.get_i32() as usize;
+        assert_eq!(4 * 5 + 5 + 2 + "TAGS\u{1}TagA\u{2}".len(), store_size);
+        buf.advance(store_size - 4);
+        assert_eq!(4 * 5 + 6 + 2, buf.get_i32());
+    }
+
+    #[test]
+    fn test_decode_compressed() {
+        let expected = "Hello RocketMQ ".repeat(100);
+        for compression_type in [
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+            CompressionType::Zlib,
+        ] {
+            let mut message = message_ext("TopicTest", b"");
+            message.message.body = compression_type
+                .compress(expected.as_bytes(), 5)
+                .unwrap()
+                .into();
+            message.sys_flag = sys_flag::COMPRESSED | compression_type.sys_flag();
+            let decoded = decode(&mut encode(&message).unwrap()).unwrap();
+            assert_eq!(expected.as_bytes(), &decoded.message.body[..]);
+            assert_eq!(0, decoded.sys_flag & !sys_flag::STOREHOST_V6);
+
+            // The decoded message encodes with its plain body, which decodes the same.
+            let mut again = decode(&mut encode(&decoded).unwrap()).unwrap();
+            assert!(again.store_size > decoded.store_size);
+            again.store_size = decoded.store_size;
+            assert_eq!(decoded, again);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Message ids: the unique ids clients give to messages they send, `MessageClientIDSetter`
+//! in Java, and the offset ids brokers derive from where they stored a message.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{Datelike, Local, Months, NaiveDate, NaiveTime, TimeDelta, TimeZone};
+
+use crate::util::Error;
+
+use super::message::{property, Message};
+
+/// Generates unique message ids: the IP address, process id and a random stand-in for
+/// the class loader hash of Java, then the milliseconds since the start of the month in
+/// the local time zone and a counter, all as upper case hex.
+pub struct MessageIdGenerator {
+    prefix: String,
+    counter: AtomicU16,
+    /// Start of the current month and of the next one, in milliseconds since the epoch.
+    month: Mutex<(i64, i64)>,
+}
+
+impl MessageIdGenerator {
+    pub fn new(ip: IpAddr) -> Self {
+        let mut prefix = Vec::with_capacity(16 + 2 + 4);
+        match ip {
+            IpAddr::V4(ip) => prefix.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => prefix.extend_from_slice(&ip.octets()),
+        }
+        prefix.extend_from_slice(&(std::process::id() as u16).to_be_bytes());
+        let random = RandomState::new().build_hasher().finish() as u32;
+        prefix.extend_from_slice(&random.to_be_bytes());
+        Self {
+            prefix: to_hex(&prefix),
+            counter: AtomicU16::new(0),
+            month: Mutex::new((0, 0)),
+        }
+    }
+
+    /// A generator for the address of this host, or loopback if it has none.
+    pub fn local() -> Self {
+        Self::new(local_ip())
+    }
+
+    pub fn next_id(&self) -> String {
+        let now = now_millis();
+        let elapsed = {
+            let mut month = self.month.lock().unwrap();
+            if now >= month.1 {
+                *month = month_bounds(now, &Local);
+            }
+            now - month.0
+        };
+        let mut suffix = [0; 6];
+        suffix[..4].copy_from_slice(&(elapsed as i32).to_be_bytes());
+        suffix[4..].copy_from_slice(&self.counter.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        let mut id = self.prefix.clone();
+        id.push_str(&to_hex(&suffix));
+        id
+    }
+}
+
+/// Gives `message` a unique id from a generator shared by the process, unless it has one.
+pub fn set_uniq_id(message: &mut Message) {
+    static GENERATOR: OnceLock<MessageIdGenerator> = OnceLock::new();
+    if message
+        .property(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+        .is_none()
+    {
+        let id = GENERATOR.get_or_init(MessageIdGenerator::local).next_id();
+        message.put_property(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX, id);
+    }
+}
+
+/// The IP address a unique message id was generated on.
+pub fn uniq_id_ip(id: &str) -> Result<IpAddr, Error> {
+    let bytes = from_hex(id)?;
+    match bytes.len() {
+        16 => Ok(Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[..4]).unwrap()).into()),
+        28 => Ok(Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[..16]).unwrap()).into()),
+        _ => Err(bad_id(id)),
+    }
+}
+
+/// Where a broker stored a message, as encoded in its offset message id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageId {
+    pub store_host: SocketAddr,
+    pub commit_log_offset: i64,
+}
+
+impl MessageId {
+    pub fn new(store_host: SocketAddr, commit_log_offset: i64) -> Self {
+        Self {
+            store_host,
+            commit_log_offset,
+        }
+    }
+
+    /// Encodes the id like brokers do: IP address, port and commit log offset, as upper
+    /// case hex. 32 characters long for IPv4 hosts and 56 for IPv6 hosts.
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::with_capacity(16 + 4 + 8);
+        match self.store_host.ip() {
+            IpAddr::V4(ip) => bytes.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => bytes.extend_from_slice(&ip.octets()),
+        }
+        bytes.extend_from_slice(&(self.store_host.port() as i32).to_be_bytes());
+        bytes.extend_from_slice(&self.commit_log_offset.to_be_bytes());
+        to_hex(&bytes)
+    }
+
+    pub fn decode(id: &str) -> Result<Self, Error> {
+        let bytes = from_hex(id)?;
+        let (ip, rest): (IpAddr, _) = match bytes.len() {
+            16 => (
+                Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[..4]).unwrap()).into(),
+                &bytes[4..],
+            ),
+            28 => (
+                Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[..16]).unwrap()).into(),
+                &bytes[16..],
+            ),
+            _ => return Err(bad_id(id)),
+        };
+        let port = i32::from_be_bytes(rest[..4].try_into().unwrap());
+        let port = u16::try_from(port).map_err(|_| bad_id(id))?;
+        Ok(Self {
+            store_host: SocketAddr::new(ip, port),
+            commit_log_offset: i64::from_be_bytes(rest[4..].try_into().unwrap()),
+        })
+    }
+}
+
+fn local_ip() -> IpAddr {
+    if_addrs::get_if_addrs()
+        .ok()
+        .and_then(|interfaces| pick_ip(interfaces.iter().map(|interface| interface.ip())))
+        .unwrap_or(Ipv4Addr::LOCALHOST.into())
+}
+
+/// Picks the address of this host to put into ids, like `UtilAll.getIP` in Java: a public
+/// IPv4 address over a private one and any IPv4 address over an IPv6 one. Loopback and
+/// link-local addresses are never picked.
+fn pick_ip(addrs: impl IntoIterator<Item = IpAddr>) -> Option<IpAddr> {
+    addrs
+        .into_iter()
+        .filter(|ip| match ip {
+            IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_unspecified() && !ip.is_link_local(),
+            IpAddr::V6(ip) => {
+                !ip.is_loopback() && !ip.is_unspecified() && ip.segments()[0] & 0xffc0 != 0xfe80
+            }
+        })
+        .min_by_key(|ip| match ip {
+            IpAddr::V4(ip) if !ip.is_private() => 0,
+            IpAddr::V4(_) => 1,
+            IpAddr::V6(_) => 2,
+        })
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// Starts of the month `millis` falls in and of the next one, in `tz`. Java takes them
+/// from a `Calendar` in the default time zone, so ids only agree on their time with
+/// Java-made ones in [`Local`] time.
+fn month_bounds<Tz: TimeZone>(millis: i64, tz: &Tz) -> (i64, i64) {
+    let start = tz
+        .timestamp_millis_opt(millis)
+        .single()
+        .map_or(NaiveDate::MIN, |time| time.date_naive())
+        .with_day(1)
+        .unwrap();
+    let next = start
+        .checked_add_months(Months::new(1))
+        .unwrap_or(NaiveDate::MAX);
+    (start_of_day(start, tz), start_of_day(next, tz))
+}
+
+/// The first instant of `date` in `tz`, in milliseconds since the epoch. That is after
+/// midnight on days the clocks skip it.
+fn start_of_day<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> i64 {
+    let mut time = date.and_time(NaiveTime::MIN);
+    loop {
+        if let Some(start) = tz.from_local_datetime(&time).earliest() {
+            return start.timestamp_millis();
+        }
+        time += TimeDelta::minutes(1);
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(hex, "{:02X}", b).unwrap();
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(bad_id(hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| bad_id(hex)))
+        .collect()
+}
+
+fn bad_id(id: &str) -> Error {
+    Error::DecodeCommandError(format!("bad message id: {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_offset_message_id() {
+        // Written by a broker at 127.0.0.1:10911.
+        let id = MessageId::decode("7F00000100002A9F00000000000C0D50").unwrap();
+        assert_eq!(
+            "127.0.0.1:10911".parse::<SocketAddr>().unwrap(),
+            id.store_host
+        );
+        assert_eq!(0xC0D50, id.commit_log_offset);
+        assert_eq!("7F00000100002A9F00000000000C0D50", id.encode());
+
+        let id = MessageId::new("[fe80::1]:10911".parse().unwrap(), 42);
+        assert_eq!(56, id.encode().len());
+        assert_eq!(id, MessageId::decode(&id.encode()).unwrap());
+
+        for bad in ["", "7F0000010000", "7F00000100002A9F00000000000C0D5Z"] {
+            assert!(MessageId::decode(bad).is_err());
+        }
+    }
+
+    #[test]
+    fn test_unique_ids() {
+        let generator = MessageIdGenerator::new("10.0.0.2".parse().unwrap());
+        let first = generator.next_id();
+        let second = generator.next_id();
+        assert_eq!(32, first.len());
+        assert_eq!(first[..20], second[..20]);
+        assert_ne!(first, second);
+        assert_eq!("0A000002", &first[..8]);
+        assert_eq!(
+            "10.0.0.2".parse::<IpAddr>().unwrap(),
+            uniq_id_ip(&first).unwrap()
+        );
+
+        let generator = MessageIdGenerator::new("fe80::1".parse().unwrap());
+        let id = generator.next_id();
+        assert_eq!(56, id.len());
+        assert_eq!(
+            "fe80::1".parse::<IpAddr>().unwrap(),
+            uniq_id_ip(&id).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_uniq_id() {
+        let mut message = Message::new("TopicTest", b"body".to_vec());
+        set_uniq_id(&mut message);
+        let id = message
+            .property(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+            .unwrap()
+            .to_string();
+        set_uniq_id(&mut message);
+        assert_eq!(
+            Some(id.as_str()),
+            message.property(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+        );
+    }
+
+    #[test]
+    fn test_pick_ip() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(None, pick_ip([ip("127.0.0.1"), ip("::1"), ip("fe80::1")]));
+        assert_eq!(
+            Some(ip("10.0.0.5")),
+            pick_ip([ip("127.0.0.1"), ip("2001:db8::1"), ip("10.0.0.5")])
+        );
+        assert_eq!(
+            Some(ip("203.0.113.7")),
+            pick_ip([ip("192.168.1.2"), ip("203.0.113.7")])
+        );
+        // IPv6-only hosts get their IPv6 address rather than loopback.
+        assert_eq!(
+            Some(ip("2001:db8::1")),
+            pick_ip([ip("::1"), ip("fe80::1"), ip("2001:db8::1")])
+        );
+    }
+
+    #[test]
+    fn test_month_bounds() {
+        // 2024-02-15T12:00:00Z, in a leap year.
+        let (start, next) = month_bounds(1_707_998_400_000, &Utc);
+        assert_eq!(1_706_745_600_000, start); // 2024-02-01
+        assert_eq!(1_709_251_200_000, next); // 2024-03-01
+
+        // 2023-12-31T23:59:59.999Z
+        let (start, next) = month_bounds(1_704_067_199_999, &Utc);
+        assert_eq!(1_701_388_800_000, start); // 2023-12-01
+        assert_eq!(1_704_067_200_000, next); // 2024-01-01
+
+        // 2024-01-01T07:59:59.999+08:00, already in the next month.
+        let east = FixedOffset::east_opt(8 * 3600).unwrap();
+        let (start, next) = month_bounds(1_704_067_199_999, &east);
+        assert_eq!(1_704_038_400_000, start); // 2024-01-01T00:00:00+08:00
+        assert_eq!(1_706_716_800_000, next); // 2024-02-01T00:00:00+08:00
+    }
+}
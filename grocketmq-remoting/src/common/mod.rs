@@ -1 +1,9 @@
-pub mod command;
\ No newline at end of file
+pub mod body;
+pub mod code;
+pub mod command;
+pub mod compression;
+pub mod frame;
+pub mod header;
+pub mod message;
+pub mod message_id;
+mod rocketmq_serializable;
@@ -0,0 +1,158 @@
+//! The compact `ROCKETMQ` header format, as implemented by `RocketMQSerializable` in Java:
+//!
+//! ```text
+//! code(2) | language(1) | version(2) | opaque(4) | flag(4)
+//! | remark length(4) | remark
+//! | ext fields length(4) | (key length(2) | key | value length(4) | value)*
+//! ```
+//!
+//! Like the Java implementation, `code` and `version` are truncated to 16 bits.
+
+use std::collections::HashMap;
+
+use crate::util::Error;
+
+use super::command::{Header, LanguageCode, SerializeType};
+
+pub(crate) fn encode_header(header: &Header) -> Vec<u8> {
+    let mut result = Vec::with_capacity(64);
+    result.extend((header.code as i16).to_be_bytes());
+    result.push(header.language.code());
+    result.extend((header.version as i16).to_be_bytes());
+    result.extend(header.opaque.to_be_bytes());
+    result.extend(header.flag.to_be_bytes());
+    match header.remark.as_deref() {
+        Some(remark) if !remark.is_empty() => {
+            result.extend((remark.len() as u32).to_be_bytes());
+            result.extend(remark.as_bytes());
+        }
+        _ => result.extend(0u32.to_be_bytes()),
+    }
+
+    let ext_fields_index = result.len();
+    result.extend(0u32.to_be_bytes());
+    for (key, value) in &header.ext_fields {
+        result.extend((key.len() as u16).to_be_bytes());
+        result.extend(key.as_bytes());
+        result.extend((value.len() as u32).to_be_bytes());
+        result.extend(value.as_bytes());
+    }
+    let ext_fields_length = (result.len() - ext_fields_index - 4) as u32;
+    result[ext_fields_index..ext_fields_index + 4]
+        .copy_from_slice(&ext_fields_length.to_be_bytes());
+
+    result
+}
+
+pub(crate) fn decode_header(data: &[u8]) -> Result<Header, Error> {
+    let mut reader = Reader { data, position: 0 };
+    let code = i16::from_be_bytes(reader.read_array()?) as i32;
+    let language = LanguageCode::from_code(reader.read_array::<1>()?[0]);
+    let version = i16::from_be_bytes(reader.read_array()?) as i32;
+    let opaque = i32::from_be_bytes(reader.read_array()?);
+    let flag = i32::from_be_bytes(reader.read_array()?);
+
+    let remark_length = u32::from_be_bytes(reader.read_array()?) as usize;
+    let remark = if remark_length > 0 {
+        Some(reader.read_string(remark_length)?)
+    } else {
+        None
+    };
+
+    let ext_fields_length = u32::from_be_bytes(reader.read_array()?) as usize;
+    let mut ext_fields = HashMap::new();
+    let ext_fields_end = reader.position + ext_fields_length;
+    while reader.position < ext_fields_end {
+        let key_length = u16::from_be_bytes(reader.read_array()?) as usize;
+        let key = reader.read_string(key_length)?;
+        let value_length = u32::from_be_bytes(reader.read_array()?) as usize;
+        let value = reader.read_string(value_length)?;
+        ext_fields.insert(key, value);
+    }
+
+    Ok(Header {
+        code,
+        ext_fields,
+        flag,
+        language,
+        opaque,
+        remark,
+        serialize_type_current_rpc: SerializeType::Rocketmq,
+        version,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn read_bytes(&mut self, length: usize) -> Result<&[u8], Error> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| {
+                Error::DecodeCommandError(format!(
+                    "header truncated, {} bytes at {} of {}",
+                    length,
+                    self.position,
+                    self.data.len()
+                ))
+            })?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut result = [0; N];
+        result.copy_from_slice(self.read_bytes(N)?);
+        Ok(result)
+    }
+
+    fn read_string(&mut self, length: usize) -> Result<String, Error> {
+        String::from_utf8(self.read_bytes(length)?.to_vec())
+            .map_err(|_| Error::DecodeCommandError("header string is not utf-8".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_header() {
+        let mut data = vec![0x00, 0x69, 0x00, 0x01, 0xdb, 0x00, 0x00, 0x00, 0x07];
+        data.extend([0x00, 0x00, 0x00, 0x00]);
+        data.extend([0x00, 0x00, 0x00, 0x00]);
+        data.extend([0x00, 0x00, 0x00, 0x14]);
+        data.extend([0x00, 0x05]);
+        data.extend(b"topic");
+        data.extend([0x00, 0x00, 0x00, 0x09]);
+        data.extend(b"TopicTest");
+
+        let header = decode_header(&data).unwrap();
+        assert_eq!(105, header.code);
+        assert_eq!(LanguageCode::Java, header.language);
+        assert_eq!(475, header.version);
+        assert_eq!(7, header.opaque);
+        assert_eq!(0, header.flag);
+        assert!(header.remark.is_none());
+        assert_eq!("TopicTest", header.ext_fields.get("topic").unwrap());
+
+        assert_eq!(data, encode_header(&header));
+    }
+
+    #[test]
+    fn test_decode_truncated_header() {
+        let data = [
+            0x00, 0x69, 0x00, 0x01, 0xdb, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert!(matches!(
+            decode_header(&data),
+            Err(Error::DecodeCommandError(_))
+        ));
+    }
+}
@@ -0,0 +1,41 @@
+//! Interceptors of the commands a client exchanges, `RPCHook` in Java.
+
+use std::{fmt, sync::Arc};
+
+use crate::common::command::Command;
+
+/// Sees every request a [`Channel`](crate::client::Channel) sends before it is written,
+/// and every response after it is read, e.g. to sign requests.
+pub trait RpcHook: Send + Sync {
+    fn before_request(&self, remote_addr: &str, request: &mut Command);
+
+    fn after_response(&self, _remote_addr: &str, _response: &mut Command) {}
+}
+
+/// The hooks of a channel, run in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct RpcHooks(Vec<Arc<dyn RpcHook>>);
+
+impl RpcHooks {
+    pub fn push(&mut self, hook: Arc<dyn RpcHook>) {
+        self.0.push(hook);
+    }
+
+    pub fn before_request(&self, remote_addr: &str, request: &mut Command) {
+        for hook in &self.0 {
+            hook.before_request(remote_addr, request);
+        }
+    }
+
+    pub fn after_response(&self, remote_addr: &str, response: &mut Command) {
+        for hook in &self.0 {
+            hook.after_response(remote_addr, response);
+        }
+    }
+}
+
+impl fmt::Debug for RpcHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RpcHooks({})", self.0.len())
+    }
+}
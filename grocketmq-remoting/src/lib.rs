@@ -1,3 +1,14 @@
+pub mod acl;
+pub mod broker;
 pub mod client;
 pub mod common;
-pub mod util;
\ No newline at end of file
+pub mod hook;
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod namesrv;
+pub mod processor;
+pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod util;
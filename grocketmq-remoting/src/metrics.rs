@@ -0,0 +1,63 @@
+//! Metrics of client channels, recorded through the [`metrics`] facade. Nothing is
+//! recorded until the application installs a recorder, e.g. a Prometheus exporter.
+//!
+//! Requests are labeled with their request `code` and `outcome`: `response` when
+//! answered, whatever the response code, or `timeout`, `closed` and `error` when not.
+//! Connection metrics are labeled with the `addr` of the channel.
+
+use std::time::Duration;
+
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+
+use crate::util::Error;
+
+/// Histogram of the time from sending a request to the end of its wait.
+pub const REQUEST_DURATION: &str = "rocketmq_remoting_request_duration_seconds";
+/// Counter of finished requests.
+pub const REQUESTS: &str = "rocketmq_remoting_requests_total";
+/// Gauge of requests waiting for a response.
+pub const IN_FLIGHT: &str = "rocketmq_remoting_in_flight_requests";
+/// Counter of bytes written, including frame headers.
+pub const SENT_BYTES: &str = "rocketmq_remoting_sent_bytes_total";
+/// Counter of bytes read, including frame headers.
+pub const RECEIVED_BYTES: &str = "rocketmq_remoting_received_bytes_total";
+/// Counter of connections established after the first one.
+pub const RECONNECTS: &str = "rocketmq_remoting_reconnects_total";
+
+/// Registers the descriptions and units of the metrics with the installed recorder.
+pub fn describe() {
+    describe_histogram!(
+        REQUEST_DURATION,
+        Unit::Seconds,
+        "Time from sending a request until its response or failure"
+    );
+    describe_counter!(REQUESTS, "Requests that got a response or failed");
+    describe_gauge!(IN_FLIGHT, "Requests waiting for a response");
+    describe_counter!(SENT_BYTES, Unit::Bytes, "Bytes written to connections");
+    describe_counter!(RECEIVED_BYTES, Unit::Bytes, "Bytes read from connections");
+    describe_counter!(RECONNECTS, "Connections re-established after breaking");
+}
+
+pub(crate) const OUTCOME_RESPONSE: &str = "response";
+
+/// The `outcome` label of a request that ended with `error`.
+pub(crate) fn outcome(error: &Error) -> &'static str {
+    match error {
+        Error::Timeout { .. } => "timeout",
+        Error::ConnectionClosed => "closed",
+        _ => "error",
+    }
+}
+
+pub(crate) fn record_request(code: i32, outcome: &'static str, elapsed: Duration) {
+    let code = code.to_string();
+    counter!(REQUESTS, "code" => code.clone(), "outcome" => outcome).increment(1);
+    histogram!(REQUEST_DURATION, "code" => code, "outcome" => outcome)
+        .record(elapsed.as_secs_f64());
+}
+
+pub(crate) fn record_in_flight(addr: &str, in_flight: usize) {
+    gauge!(IN_FLIGHT, "addr" => addr.to_string()).set(in_flight as f64);
+}
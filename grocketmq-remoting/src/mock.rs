@@ -0,0 +1,828 @@
+//! An in-process RocketMQ server for tests, enabled with the `mock` feature.
+//!
+//! [`MockServer`] speaks the remoting protocol on loopback and answers like a name
+//! server and a single broker at once: it serves the topic routes it was given, stores
+//! sent messages in memory, and serves pulls, offsets and lookups from them. Requests
+//! can be delayed, answered with an error or left unanswered, and connections dropped,
+//! with [`Fault`]s injected by request code.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tokio::{sync::watch, time::sleep};
+
+use crate::{
+    common::{
+        body::{self, perm, BrokerData, ClusterInfo, QueueData, TopicList, TopicRouteData},
+        code::{RequestCode, ResponseCode},
+        command::Command,
+        header::{
+            broker::{
+                EndTransactionRequestHeader, GetMaxOffsetRequestHeader, GetMaxOffsetResponseHeader,
+                GetMinOffsetRequestHeader, GetMinOffsetResponseHeader, PullMessageRequestHeader,
+                PullMessageResponseHeader, QueryConsumerOffsetRequestHeader,
+                QueryConsumerOffsetResponseHeader, SearchOffsetRequestHeader,
+                SearchOffsetResponseHeader, SendMessageRequestHeader, SendMessageRequestHeaderV2,
+                SendMessageResponseHeader, UpdateConsumerOffsetRequestHeader,
+                ViewMessageRequestHeader,
+            },
+            namesrv::GetRouteInfoRequestHeader,
+        },
+        message::{self, property, Message, MessageExt},
+    },
+    processor::RequestProcessor,
+    server::{RemotingServer, ServerHandle},
+    util::Error,
+};
+
+/// Name of the broker in the routes [`MockServer::add_topic`] creates.
+pub const BROKER_NAME: &str = "broker-a";
+/// Cluster of the broker in the routes [`MockServer::add_topic`] creates.
+pub const CLUSTER_NAME: &str = "DefaultCluster";
+
+/// A failure injected into the handling of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Waits this long, then handles the request as usual.
+    Delay(Duration),
+    /// Answers with this response code and remark instead of handling the request.
+    Respond { code: i32, remark: String },
+    /// Never answers, so the request times out.
+    NoResponse,
+    /// Closes every connection without answering. The server keeps accepting new ones.
+    Disconnect,
+}
+
+impl Fault {
+    pub fn respond(code: impl Into<i32>, remark: impl Into<String>) -> Self {
+        Fault::Respond {
+            code: code.into(),
+            remark: remark.into(),
+        }
+    }
+}
+
+/// A name server and broker on loopback, for tests. Shuts down when dropped.
+///
+/// Messages sent to any topic are stored, whether it has a route or not. Pulls are
+/// answered right away, without holding the request until messages arrive, and filter
+/// by tag for `TAG` subscriptions.
+pub struct MockServer {
+    server: ServerHandle,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    /// Starts a server on a free port of 127.0.0.1.
+    pub async fn start() -> Result<Self, Error> {
+        let server = RemotingServer::bind("127.0.0.1:0").await?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let processor = MockProcessor {
+            state: state.clone(),
+            store_host: server.local_addr()?,
+            close_tx: server.close_sender(),
+        };
+        server.register_default_processor(Arc::new(processor), None);
+        Ok(Self {
+            server: server.start()?,
+            state,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+
+    /// The address to connect to, as name server or as broker.
+    pub fn addr(&self) -> String {
+        self.local_addr().to_string()
+    }
+
+    /// Routes `topic` to `queue_nums` read and write queues of this server, as the
+    /// master of [`BROKER_NAME`].
+    pub fn add_topic(&self, topic: &str, queue_nums: i32) {
+        let route = TopicRouteData {
+            queue_datas: vec![QueueData {
+                broker_name: BROKER_NAME.to_string(),
+                read_queue_nums: queue_nums,
+                write_queue_nums: queue_nums,
+                perm: perm::READ | perm::WRITE,
+                ..Default::default()
+            }],
+            broker_datas: vec![BrokerData {
+                cluster: CLUSTER_NAME.to_string(),
+                broker_name: BROKER_NAME.to_string(),
+                broker_addrs: HashMap::from([(body::MASTER_ID, self.addr())]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        self.put_route(topic, route);
+    }
+
+    /// Serves `route` for `topic`, e.g. to point clients at other servers.
+    pub fn put_route(&self, topic: &str, route: TopicRouteData) {
+        self.lock().routes.insert(topic.to_string(), route);
+    }
+
+    pub fn remove_route(&self, topic: &str) {
+        self.lock().routes.remove(topic);
+    }
+
+    /// Stores a message as if it had been sent, e.g. for a consumer to pull. Fails with
+    /// [`Error::MessageIllegal`] for messages a broker would refuse.
+    pub fn put_message(&self, message: Message, queue_id: i32) -> Result<MessageExt, Error> {
+        let store_host = self.local_addr();
+        let mut stored = MessageExt {
+            message,
+            queue_id,
+            born_timestamp: now_millis(),
+            born_host: store_host,
+            store_host,
+            ..Default::default()
+        };
+        self.lock().store(&mut stored)?;
+        Ok(stored)
+    }
+
+    /// The messages stored in a queue, oldest first.
+    pub fn messages(&self, topic: &str, queue_id: i32) -> Vec<MessageExt> {
+        let state = self.lock();
+        state
+            .queue(topic, queue_id)
+            .iter()
+            .map(|&index| state.commit_log[index].clone())
+            .collect()
+    }
+
+    /// The offset `group` committed for a queue.
+    pub fn consumer_offset(&self, group: &str, topic: &str, queue_id: i32) -> Option<i64> {
+        self.lock()
+            .consumer_offsets
+            .get(&(group.to_string(), topic.to_string(), queue_id))
+            .copied()
+    }
+
+    /// The end transaction requests received so far.
+    pub fn transactions(&self) -> Vec<EndTransactionRequestHeader> {
+        self.lock().transactions.clone()
+    }
+
+    /// How many requests with `code` were received, including those a fault was
+    /// injected into.
+    pub fn request_count(&self, code: impl Into<i32>) -> usize {
+        self.lock()
+            .request_counts
+            .get(&code.into())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Injects `fault` into the next request with `code`. Faults injected this way apply
+    /// in order, one per request, before those of [`MockServer::inject_always`].
+    pub fn inject(&self, code: impl Into<i32>, fault: Fault) {
+        self.lock()
+            .faults
+            .entry(code.into())
+            .or_default()
+            .push_back(fault);
+    }
+
+    /// Injects `fault` into every request with `code` until the faults are cleared.
+    pub fn inject_always(&self, code: impl Into<i32>, fault: Fault) {
+        self.lock().permanent_faults.insert(code.into(), fault);
+    }
+
+    pub fn clear_faults(&self) {
+        let mut state = self.lock();
+        state.faults.clear();
+        state.permanent_faults.clear();
+    }
+
+    /// Drops every open connection, see [`ServerHandle::close_connections`].
+    pub fn close_connections(&self) {
+        self.server.close_connections();
+    }
+
+    /// Answers the requests in flight, then closes all connections.
+    pub async fn shutdown(self) {
+        self.server.shutdown().await;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    routes: HashMap<String, TopicRouteData>,
+    commit_log: Vec<MessageExt>,
+    /// Where the next message goes in the commit log, in bytes.
+    commit_log_end: i64,
+    /// Indexes into `commit_log` by topic and queue id.
+    queues: HashMap<(String, i32), Vec<usize>>,
+    consumer_offsets: HashMap<(String, String, i32), i64>,
+    transactions: Vec<EndTransactionRequestHeader>,
+    faults: HashMap<i32, VecDeque<Fault>>,
+    permanent_faults: HashMap<i32, Fault>,
+    request_counts: HashMap<i32, usize>,
+}
+
+impl State {
+    /// Counts a request with `code` and takes the fault to inject into it.
+    fn receive(&mut self, code: i32) -> Option<Fault> {
+        *self.request_counts.entry(code).or_default() += 1;
+        self.faults
+            .get_mut(&code)
+            .and_then(VecDeque::pop_front)
+            .or_else(|| self.permanent_faults.get(&code).cloned())
+    }
+
+    fn queue(&self, topic: &str, queue_id: i32) -> &[usize] {
+        self.queues
+            .get(&(topic.to_string(), queue_id))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Appends `message` to the commit log and its queue, filling in the fields a broker
+    /// sets on storing. Messages that can't be encoded aren't stored.
+    fn store(&mut self, message: &mut MessageExt) -> Result<(), Error> {
+        message.queue_offset = self.queue(&message.message.topic, message.queue_id).len() as i64;
+        message.commit_log_offset = self.commit_log_end;
+        message.store_timestamp = now_millis();
+        message.body_crc = (crc32fast::hash(&message.message.body) & 0x7FFF_FFFF) as i32;
+        message.store_size = message::encode(message)?.len() as i32;
+        self.queues
+            .entry((message.message.topic.clone(), message.queue_id))
+            .or_default()
+            .push(self.commit_log.len());
+        self.commit_log_end += message.store_size as i64;
+        self.commit_log.push(message.clone());
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        store_host: SocketAddr,
+        remote_addr: SocketAddr,
+        request: Command,
+    ) -> Result<Option<Command>, Error> {
+        let Ok(code) = RequestCode::try_from(request.code()) else {
+            return Ok(Some(not_supported(request.code())));
+        };
+        let response = match code {
+            RequestCode::GetRouteInfoByTopic => {
+                let header: GetRouteInfoRequestHeader = request.decode_header()?;
+                match self.routes.get(&header.topic) {
+                    Some(route) => json_response(route),
+                    None => {
+                        let mut response = Command::new_response(ResponseCode::TopicNotExist);
+                        response.set_remark(format!(
+                            "No topic route info in name server for the topic: {}",
+                            header.topic
+                        ));
+                        response
+                    }
+                }
+            }
+            RequestCode::GetBrokerClusterInfo => {
+                let mut cluster = ClusterInfo::default();
+                for broker in self.routes.values().flat_map(|route| &route.broker_datas) {
+                    cluster
+                        .cluster_addr_table
+                        .entry(broker.cluster.clone())
+                        .or_insert_with(HashSet::new)
+                        .insert(broker.broker_name.clone());
+                    cluster
+                        .broker_addr_table
+                        .insert(broker.broker_name.clone(), broker.clone());
+                }
+                json_response(&cluster)
+            }
+            RequestCode::GetAllTopicListFromNameServer => json_response(&TopicList {
+                topic_list: self.routes.keys().cloned().collect(),
+                ..Default::default()
+            }),
+            RequestCode::SendMessage => {
+                let header: SendMessageRequestHeader = request.decode_header()?;
+                self.send(store_host, remote_addr, header, request)?
+            }
+            RequestCode::SendMessageV2 | RequestCode::SendBatchMessage => {
+                let header: SendMessageRequestHeaderV2 = request.decode_header()?;
+                let mut header = SendMessageRequestHeader::from(header);
+                if code == RequestCode::SendBatchMessage {
+                    header.batch = Some(true);
+                }
+                self.send(store_host, remote_addr, header, request)?
+            }
+            RequestCode::PullMessage => self.pull(request.decode_header()?),
+            RequestCode::QueryConsumerOffset => {
+                let header: QueryConsumerOffsetRequestHeader = request.decode_header()?;
+                let key = (header.consumer_group, header.topic, header.queue_id);
+                match self.consumer_offsets.get(&key) {
+                    Some(&offset) => Command::new_response(ResponseCode::Success)
+                        .with_header(&QueryConsumerOffsetResponseHeader { offset }),
+                    None if header.set_zero_if_not_found == Some(true) => {
+                        Command::new_response(ResponseCode::Success)
+                            .with_header(&QueryConsumerOffsetResponseHeader { offset: 0 })
+                    }
+                    None => {
+                        let mut response = Command::new_response(ResponseCode::QueryNotFound);
+                        response.set_remark(
+                            "Not found, do not set to zero, maybe this group consumer boot first",
+                        );
+                        response
+                    }
+                }
+            }
+            RequestCode::UpdateConsumerOffset => {
+                let header: UpdateConsumerOffsetRequestHeader = request.decode_header()?;
+                self.consumer_offsets.insert(
+                    (header.consumer_group, header.topic, header.queue_id),
+                    header.commit_offset,
+                );
+                Command::new_response(ResponseCode::Success)
+            }
+            RequestCode::GetMaxOffset => {
+                let header: GetMaxOffsetRequestHeader = request.decode_header()?;
+                let offset = self.queue(&header.topic, header.queue_id).len() as i64;
+                Command::new_response(ResponseCode::Success)
+                    .with_header(&GetMaxOffsetResponseHeader { offset })
+            }
+            RequestCode::GetMinOffset => {
+                let _: GetMinOffsetRequestHeader = request.decode_header()?;
+                Command::new_response(ResponseCode::Success)
+                    .with_header(&GetMinOffsetResponseHeader { offset: 0 })
+            }
+            RequestCode::SearchOffsetByTimestamp => {
+                let header: SearchOffsetRequestHeader = request.decode_header()?;
+                let queue = self.queue(&header.topic, header.queue_id);
+                let offset = queue
+                    .iter()
+                    .position(|&index| self.commit_log[index].store_timestamp >= header.timestamp)
+                    .unwrap_or(queue.len()) as i64;
+                Command::new_response(ResponseCode::Success)
+                    .with_header(&SearchOffsetResponseHeader { offset })
+            }
+            RequestCode::ViewMessageById => {
+                let header: ViewMessageRequestHeader = request.decode_header()?;
+                match self
+                    .commit_log
+                    .iter()
+                    .find(|message| message.commit_log_offset == header.offset)
+                {
+                    Some(message) => {
+                        let mut response = Command::new_response(ResponseCode::Success);
+                        response.set_body(message::encode(message)?);
+                        response
+                    }
+                    None => {
+                        let mut response = Command::new_response(ResponseCode::SystemError);
+                        response.set_remark(format!(
+                            "can not find message by the offset, {}",
+                            header.offset
+                        ));
+                        response
+                    }
+                }
+            }
+            RequestCode::EndTransaction => {
+                self.transactions.push(request.decode_header()?);
+                return Ok(None);
+            }
+            RequestCode::HeartBeat | RequestCode::UnregisterClient => {
+                Command::new_response(ResponseCode::Success)
+            }
+            _ => not_supported(request.code()),
+        };
+        Ok(Some(response))
+    }
+
+    fn send(
+        &mut self,
+        store_host: SocketAddr,
+        born_host: SocketAddr,
+        header: SendMessageRequestHeader,
+        request: Command,
+    ) -> Result<Command, Error> {
+        let body = request.body().cloned().unwrap_or_default();
+        let messages = if header.batch == Some(true) {
+            message::decode_batch(body)?
+        } else {
+            vec![Message {
+                flag: header.flag,
+                properties: message::string_to_properties(
+                    header.properties.as_deref().unwrap_or_default(),
+                ),
+                body,
+                ..Default::default()
+            }]
+        };
+        let mut stored = messages
+            .into_iter()
+            .map(|mut message| {
+                message.topic = header.topic.clone();
+                MessageExt {
+                    message,
+                    queue_id: header.queue_id,
+                    sys_flag: header.sys_flag,
+                    born_timestamp: header.born_timestamp,
+                    born_host,
+                    store_host,
+                    reconsume_times: header.reconsume_times.unwrap_or(0),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+        // Like a broker, a batch is refused as a whole if any of its messages is illegal.
+        if let Err(e) = stored
+            .iter()
+            .try_for_each(|message| message::encode(message).map(drop))
+        {
+            let mut response = Command::new_response(ResponseCode::MessageIllegal);
+            response.set_remark(e.to_string());
+            return Ok(response);
+        }
+        for message in &mut stored {
+            self.store(message)?;
+        }
+        let Some(first) = stored.first() else {
+            let mut response = Command::new_response(ResponseCode::MessageIllegal);
+            response.set_remark("the batch is empty");
+            return Ok(response);
+        };
+        // Like a broker, batches are answered with the ids of all their messages.
+        let msg_id = stored
+            .iter()
+            .map(MessageExt::offset_msg_id)
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(
+            Command::new_response(ResponseCode::Success).with_header(&SendMessageResponseHeader {
+                msg_id,
+                queue_id: header.queue_id,
+                queue_offset: first.queue_offset,
+                transaction_id: first
+                    .message
+                    .property(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+                    .map(str::to_string),
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn pull(&self, header: PullMessageRequestHeader) -> Command {
+        let queue = self.queue(&header.topic, header.queue_id);
+        let max_offset = queue.len() as i64;
+        let mut response_header = PullMessageResponseHeader {
+            next_begin_offset: header.queue_offset,
+            min_offset: 0,
+            max_offset,
+            ..Default::default()
+        };
+        if header.queue_offset < 0 || header.queue_offset > max_offset {
+            response_header.next_begin_offset = max_offset;
+            return Command::new_response(ResponseCode::PullOffsetMoved)
+                .with_header(&response_header);
+        }
+        if header.queue_offset == max_offset {
+            return Command::new_response(ResponseCode::PullNotFound).with_header(&response_header);
+        }
+
+        let tags = subscribed_tags(&header);
+        let mut body = BytesMut::new();
+        let scanned = queue[header.queue_offset as usize..]
+            .iter()
+            .map(|&index| &self.commit_log[index])
+            .take(header.max_msg_nums.max(1) as usize);
+        for message in scanned {
+            response_header.next_begin_offset += 1;
+            let matches = match (&tags, message.message.tags()) {
+                (None, _) => true,
+                (Some(tags), Some(tag)) => tags.contains(tag),
+                (Some(_), None) => false,
+            };
+            if matches {
+                // Stored messages were encoded once already.
+                body.extend_from_slice(&message::encode(message).unwrap());
+            }
+        }
+        if body.is_empty() {
+            return Command::new_response(ResponseCode::PullRetryImmediately)
+                .with_header(&response_header);
+        }
+        let mut response =
+            Command::new_response(ResponseCode::Success).with_header(&response_header);
+        response.set_body(body.freeze());
+        response
+    }
+}
+
+/// The tags a pull subscribes to, or `None` for all messages.
+fn subscribed_tags(header: &PullMessageRequestHeader) -> Option<HashSet<&str>> {
+    if !matches!(header.expression_type.as_deref(), None | Some("TAG")) {
+        return None;
+    }
+    let expression = header.subscription.as_deref()?.trim();
+    if expression.is_empty() || expression == "*" {
+        return None;
+    }
+    Some(expression.split("||").map(str::trim).collect())
+}
+
+struct MockProcessor {
+    state: Arc<Mutex<State>>,
+    store_host: SocketAddr,
+    close_tx: Arc<watch::Sender<()>>,
+}
+
+#[async_trait]
+impl RequestProcessor for MockProcessor {
+    async fn process(
+        &self,
+        remote_addr: SocketAddr,
+        request: Command,
+    ) -> Result<Option<Command>, Error> {
+        let fault = self.state.lock().unwrap().receive(request.code());
+        match fault {
+            Some(Fault::Delay(delay)) => sleep(delay).await,
+            Some(Fault::Respond { code, remark }) => {
+                let mut response = Command::new_response(code);
+                response.set_remark(remark);
+                return Ok(Some(response));
+            }
+            Some(Fault::NoResponse) => return Ok(None),
+            Some(Fault::Disconnect) => {
+                self.close_tx.send_replace(());
+                return Ok(None);
+            }
+            None => {}
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .handle(self.store_host, remote_addr, request)
+    }
+}
+
+fn json_response<T: serde::Serialize>(body: &T) -> Command {
+    let mut response = Command::new_response(ResponseCode::Success);
+    response.set_body(body::encode(body));
+    response
+}
+
+fn not_supported(code: i32) -> Command {
+    let mut response = Command::new_response(ResponseCode::RequestCodeNotSupported);
+    response.set_remark(format!(" request type {} not supported", code));
+    response
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        broker::{BrokerClient, PullStatus},
+        client::{RemotingClient, RemotingClientConfig},
+        common::header::broker::QueryConsumerOffsetRequestHeader,
+        namesrv::NameServerClient,
+    };
+
+    fn clients(server: &MockServer) -> (NameServerClient, BrokerClient) {
+        let client = RemotingClient::new(RemotingClientConfig {
+            request_timeout: Duration::from_millis(200),
+            ..Default::default()
+        });
+        client.update_name_server_addresses(vec![server.addr()]);
+        (
+            NameServerClient::new(client.clone()),
+            BrokerClient::new(client),
+        )
+    }
+
+    fn send_header(queue_id: i32) -> SendMessageRequestHeader {
+        SendMessageRequestHeader {
+            producer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            queue_id,
+            ..Default::default()
+        }
+    }
+
+    fn pull_header(queue_offset: i64) -> PullMessageRequestHeader {
+        PullMessageRequestHeader {
+            consumer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            queue_id: 1,
+            queue_offset,
+            max_msg_nums: 32,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_send_and_pull() {
+        let server = MockServer::start().await.unwrap();
+        server.add_topic("TopicTest", 4);
+        let (namesrv, broker) = clients(&server);
+
+        let route = namesrv.get_route_info_by_topic("TopicTest").await.unwrap();
+        let addr = route.broker_datas[0].master_addr().unwrap().to_string();
+        assert_eq!(server.addr(), addr);
+        assert!(namesrv.get_route_info_by_topic("Unknown").await.is_err());
+        let cluster = namesrv.get_broker_cluster_info().await.unwrap();
+        assert!(cluster.cluster_addr_table[CLUSTER_NAME].contains(BROKER_NAME));
+
+        let mut header = send_header(1);
+        header.properties = Some(message::properties_to_string(&HashMap::from([(
+            property::TAGS.to_string(),
+            "TagA".to_string(),
+        )])));
+        let result = broker
+            .send_message(&addr, &header, b"first".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(0, result.queue_offset);
+        let batch = message::encode_batch(&[
+            Message::new("", b"second".to_vec()),
+            Message::new("", b"third".to_vec()),
+        ])
+        .unwrap();
+        let result = broker
+            .send_batch(&addr, &send_header(1), batch)
+            .await
+            .unwrap();
+        assert_eq!(1, result.queue_offset);
+        assert_eq!(2, result.offset_msg_id.split(',').count());
+        assert_eq!(3, server.messages("TopicTest", 1).len());
+
+        let result = broker
+            .pull_message(&addr, &pull_header(0), Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(PullStatus::Found, result.status);
+        assert_eq!(3, result.next_begin_offset);
+        let bodies: Vec<_> = result
+            .messages()
+            .unwrap()
+            .into_iter()
+            .map(|message| message.message.body)
+            .collect();
+        assert_eq!(vec!["first", "second", "third"], bodies);
+
+        let mut header = pull_header(1);
+        header.subscription = Some("TagA || TagB".to_string());
+        let result = broker
+            .pull_message(&addr, &header, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(PullStatus::NoMatchedMsg, result.status);
+        assert_eq!(3, result.next_begin_offset);
+        let result = broker
+            .pull_message(&addr, &pull_header(3), Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(PullStatus::NoNewMsg, result.status);
+        let result = broker
+            .pull_message(&addr, &pull_header(7), Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(PullStatus::OffsetIllegal, result.status);
+
+        let stored = server
+            .put_message(Message::new("TopicTest", b"viewed".to_vec()), 2)
+            .unwrap();
+        let viewed = broker.view_message(&stored.offset_msg_id()).await.unwrap();
+        assert_eq!(stored, viewed);
+    }
+
+    #[tokio::test]
+    async fn test_offsets() {
+        let server = MockServer::start().await.unwrap();
+        let (_, broker) = clients(&server);
+        let addr = server.addr();
+
+        let header = QueryConsumerOffsetRequestHeader {
+            consumer_group: "group".to_string(),
+            topic: "TopicTest".to_string(),
+            queue_id: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            None,
+            broker.query_consumer_offset(&addr, &header).await.unwrap()
+        );
+        broker
+            .update_consumer_offset(
+                &addr,
+                &UpdateConsumerOffsetRequestHeader {
+                    consumer_group: "group".to_string(),
+                    topic: "TopicTest".to_string(),
+                    queue_id: 1,
+                    commit_offset: 5,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(5),
+            broker.query_consumer_offset(&addr, &header).await.unwrap()
+        );
+        assert_eq!(Some(5), server.consumer_offset("group", "TopicTest", 1));
+
+        server
+            .put_message(Message::new("TopicTest", b"a".to_vec()), 1)
+            .unwrap();
+        server
+            .put_message(Message::new("TopicTest", b"b".to_vec()), 1)
+            .unwrap();
+        let header = GetMaxOffsetRequestHeader {
+            topic: "TopicTest".to_string(),
+            queue_id: 1,
+        };
+        assert_eq!(2, broker.get_max_offset(&addr, &header).await.unwrap());
+        let header = SearchOffsetRequestHeader {
+            topic: "TopicTest".to_string(),
+            queue_id: 1,
+            timestamp: now_millis() + 60_000,
+        };
+        assert_eq!(
+            2,
+            broker
+                .search_offset_by_timestamp(&addr, &header)
+                .await
+                .unwrap()
+        );
+
+        let header = EndTransactionRequestHeader {
+            producer_group: "group".to_string(),
+            ..Default::default()
+        };
+        broker.end_transaction(&addr, &header, None).await.unwrap();
+        // Oneway, so nothing tells when the server handled it.
+        timeout(Duration::from_secs(3), async {
+            while server.transactions().is_empty() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(vec![header], server.transactions());
+    }
+
+    #[tokio::test]
+    async fn test_faults() {
+        let server = MockServer::start().await.unwrap();
+        let (_, broker) = clients(&server);
+        let addr = server.addr();
+        let send = || async {
+            broker
+                .send_message(&addr, &send_header(0), b"body".to_vec())
+                .await
+        };
+
+        server.inject(
+            RequestCode::SendMessageV2,
+            Fault::respond(ResponseCode::SystemBusy, "busy"),
+        );
+        server.inject(RequestCode::SendMessageV2, Fault::NoResponse);
+        assert!(matches!(
+            send().await,
+            Err(Error::RemoteError { code: 2, remark }) if remark == "busy"
+        ));
+        assert!(matches!(send().await, Err(Error::Timeout { .. })));
+        send().await.unwrap();
+
+        server.inject_always(
+            RequestCode::SendMessageV2,
+            Fault::Delay(Duration::from_millis(500)),
+        );
+        assert!(matches!(send().await, Err(Error::Timeout { .. })));
+        server.clear_faults();
+
+        server.inject(RequestCode::SendMessageV2, Fault::Disconnect);
+        assert!(matches!(send().await, Err(Error::ConnectionClosed)));
+        // The broken channel is replaced on the next request.
+        send().await.unwrap();
+        assert_eq!(6, server.request_count(RequestCode::SendMessageV2));
+        // The delayed message is stored once its delay is over, though it timed out.
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(3, server.messages("TopicTest", 0).len());
+        server.shutdown().await;
+    }
+}
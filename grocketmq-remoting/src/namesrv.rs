@@ -0,0 +1,283 @@
+//! Typed requests to name servers.
+
+use bytes::Bytes;
+use futures_util::future::join_all;
+
+use crate::{
+    client::RemotingClient,
+    common::{
+        body::{self, ClusterInfo, KvTable, RegisterBrokerBody, TopicList, TopicRouteData},
+        code::RequestCode,
+        command::Command,
+        header::namesrv::{
+            GetRouteInfoRequestHeader, RegisterBrokerRequestHeader, RegisterBrokerResponseHeader,
+            UnRegisterBrokerRequestHeader,
+        },
+    },
+    util::Error,
+};
+
+/// What a name server answered to a broker registration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegisterBrokerResult {
+    pub ha_server_addr: Option<String>,
+    pub master_addr: Option<String>,
+    pub kv_table: KvTable,
+}
+
+/// Sends typed requests to the name servers configured on a [`RemotingClient`].
+///
+/// Lookups go to one name server and fail over to the next, while registrations go to
+/// every name server, like `BrokerOuterAPI` in Java.
+#[derive(Clone)]
+pub struct NameServerClient {
+    client: RemotingClient,
+}
+
+impl NameServerClient {
+    pub fn new(client: RemotingClient) -> Self {
+        Self { client }
+    }
+
+    pub fn remoting_client(&self) -> &RemotingClient {
+        &self.client
+    }
+
+    /// Looks up the brokers and queues of `topic`. Fails with
+    /// [`Error::RemoteError`] of code `TOPIC_NOT_EXIST` for unknown topics.
+    pub async fn get_route_info_by_topic(&self, topic: &str) -> Result<TopicRouteData, Error> {
+        let header = GetRouteInfoRequestHeader {
+            topic: topic.to_string(),
+            accept_standard_json_only: Some(true),
+        };
+        let response = self
+            .client
+            .invoke_name_server(|| {
+                Command::new(RequestCode::GetRouteInfoByTopic).with_header(&header)
+            })
+            .await?;
+        json_body(response)
+    }
+
+    pub async fn get_broker_cluster_info(&self) -> Result<ClusterInfo, Error> {
+        let response = self
+            .client
+            .invoke_name_server(|| Command::new(RequestCode::GetBrokerClusterInfo))
+            .await?;
+        json_body(response)
+    }
+
+    pub async fn get_all_topic_list(&self) -> Result<TopicList, Error> {
+        let response = self
+            .client
+            .invoke_name_server(|| Command::new(RequestCode::GetAllTopicListFromNameServer))
+            .await?;
+        json_body(response)
+    }
+
+    /// Registers a broker with every name server. `compressed` and `body_crc32` of the
+    /// header are filled in from `body`. Returns one result per name server, in the
+    /// order of the configured addresses.
+    pub async fn register_broker(
+        &self,
+        mut header: RegisterBrokerRequestHeader,
+        body: &RegisterBrokerBody,
+    ) -> Vec<Result<RegisterBrokerResult, Error>> {
+        let body = Bytes::from(body::encode(body));
+        header.compressed = false;
+        header.body_crc32 = (crc32fast::hash(&body) & 0x7FFF_FFFF) as i32;
+
+        let requests = self.client.name_server_addresses().into_iter().map(|addr| {
+            let mut request = Command::new(RequestCode::RegisterBroker).with_header(&header);
+            request.set_body(body.clone());
+            async move {
+                let response = self.client.invoke(&addr, request).await?.ensure_success()?;
+                let response_header: RegisterBrokerResponseHeader = response.decode_header()?;
+                let kv_table = match response.body() {
+                    Some(body) => body::decode(body)?,
+                    None => KvTable::default(),
+                };
+                Ok(RegisterBrokerResult {
+                    ha_server_addr: response_header.ha_server_addr,
+                    master_addr: response_header.master_addr,
+                    kv_table,
+                })
+            }
+        });
+        join_all(requests).await
+    }
+
+    /// Unregisters a broker from every name server. Returns one result per name server,
+    /// in the order of the configured addresses.
+    pub async fn unregister_broker(
+        &self,
+        header: &UnRegisterBrokerRequestHeader,
+    ) -> Vec<Result<(), Error>> {
+        let requests = self.client.name_server_addresses().into_iter().map(|addr| {
+            let request = Command::new(RequestCode::UnregisterBroker).with_header(header);
+            async move {
+                self.client.invoke(&addr, request).await?.ensure_success()?;
+                Ok(())
+            }
+        });
+        join_all(requests).await
+    }
+}
+
+fn json_body<T: serde::de::DeserializeOwned>(response: Command) -> Result<T, Error> {
+    let response = response.ensure_success()?;
+    let body = response
+        .body()
+        .ok_or_else(|| Error::DecodeCommandError("missing body".to_string()))?;
+    body::decode(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        client::RemotingClientConfig,
+        common::{
+            body::{BrokerData, TopicConfig},
+            code::ResponseCode,
+        },
+        processor::RequestProcessor,
+        server::{RemotingServer, ServerHandle},
+    };
+
+    /// Answers like a 4.x name server knowing a single topic.
+    struct FakeNameServer;
+
+    #[async_trait]
+    impl RequestProcessor for FakeNameServer {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            request: Command,
+        ) -> Result<Option<Command>, Error> {
+            let mut response = Command::new_response(ResponseCode::Success);
+            match RequestCode::try_from(request.code())? {
+                RequestCode::GetRouteInfoByTopic => {
+                    let header: GetRouteInfoRequestHeader = request.decode_header()?;
+                    assert_eq!(Some(true), header.accept_standard_json_only);
+                    if header.topic != "TopicTest" {
+                        let mut response = Command::new_response(ResponseCode::TopicNotExist);
+                        response.set_remark(format!("No topic route info for {}", header.topic));
+                        return Ok(Some(response));
+                    }
+                    response.set_body(
+                        b"{\"brokerDatas\":[{\"brokerAddrs\":{0:\"127.0.0.1:10911\"},\
+\"brokerName\":\"broker-a\",\"cluster\":\"DefaultCluster\"}],\"filterServerTable\":{},\
+\"queueDatas\":[{\"brokerName\":\"broker-a\",\"perm\":6,\"readQueueNums\":4,\
+\"topicSysFlag\":0,\"writeQueueNums\":4}]}"
+                            .to_vec(),
+                    );
+                }
+                RequestCode::GetBrokerClusterInfo => {
+                    response.set_body(
+                        b"{\"brokerAddrTable\":{\"broker-a\":{\"brokerAddrs\":{0:\"127.0.0.1:10911\"},\
+\"brokerName\":\"broker-a\",\"cluster\":\"DefaultCluster\"}},\
+\"clusterAddrTable\":{\"DefaultCluster\":[\"broker-a\"]}}"
+                            .to_vec(),
+                    );
+                }
+                RequestCode::GetAllTopicListFromNameServer => {
+                    response.set_body(b"{\"topicList\":[\"TopicTest\"]}".to_vec());
+                }
+                RequestCode::RegisterBroker => {
+                    let header: RegisterBrokerRequestHeader = request.decode_header()?;
+                    let body = request.body().unwrap();
+                    assert_eq!(
+                        (crc32fast::hash(body) & 0x7FFF_FFFF) as i32,
+                        header.body_crc32
+                    );
+                    let body: RegisterBrokerBody = body::decode(body)?;
+                    assert!(body
+                        .topic_config_serialize_wrapper
+                        .topic_config_table
+                        .contains_key("TopicTest"));
+                    response.add_property("masterAddr", "127.0.0.1:10911");
+                    response.set_body(b"{\"table\":{\"orderConf\":\"\"}}".to_vec());
+                }
+                RequestCode::UnregisterBroker => {}
+                _ => {
+                    return Ok(Some(Command::new_response(
+                        ResponseCode::RequestCodeNotSupported,
+                    )))
+                }
+            }
+            Ok(Some(response))
+        }
+    }
+
+    async fn start() -> (ServerHandle, NameServerClient) {
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_default_processor(Arc::new(FakeNameServer), None);
+        let server = server.start().unwrap();
+        let client = RemotingClient::new(RemotingClientConfig::default());
+        client.update_name_server_addresses(vec![server.local_addr().to_string()]);
+        (server, NameServerClient::new(client))
+    }
+
+    #[tokio::test]
+    async fn test_lookups() {
+        let (_server, client) = start().await;
+
+        let route = client.get_route_info_by_topic("TopicTest").await.unwrap();
+        assert_eq!(Some("127.0.0.1:10911"), route.broker_datas[0].master_addr());
+        assert_eq!(4, route.queue_datas[0].write_queue_nums);
+
+        let error = client.get_route_info_by_topic("Unknown").await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::RemoteError { code, .. } if code == ResponseCode::TopicNotExist.code()
+        ));
+
+        let cluster = client.get_broker_cluster_info().await.unwrap();
+        let broker: &BrokerData = &cluster.broker_addr_table["broker-a"];
+        assert_eq!("DefaultCluster", broker.cluster);
+        assert!(cluster.cluster_addr_table["DefaultCluster"].contains("broker-a"));
+
+        let topics = client.get_all_topic_list().await.unwrap();
+        assert!(topics.topic_list.contains("TopicTest"));
+    }
+
+    #[tokio::test]
+    async fn test_register_broker() {
+        let (_server, client) = start().await;
+        let mut body = RegisterBrokerBody::default();
+        body.topic_config_serialize_wrapper
+            .topic_config_table
+            .insert(
+                "TopicTest".to_string(),
+                TopicConfig {
+                    topic_name: "TopicTest".to_string(),
+                    ..Default::default()
+                },
+            );
+        let header = RegisterBrokerRequestHeader {
+            broker_name: "broker-a".to_string(),
+            broker_addr: "127.0.0.1:10911".to_string(),
+            cluster_name: "DefaultCluster".to_string(),
+            ..Default::default()
+        };
+
+        let results = client.register_broker(header, &body).await;
+        assert_eq!(1, results.len());
+        let result = results[0].as_ref().unwrap();
+        assert_eq!(Some("127.0.0.1:10911"), result.master_addr.as_deref());
+        assert_eq!("", result.kv_table.table["orderConf"]);
+
+        let results = client
+            .unregister_broker(&UnRegisterBrokerRequestHeader {
+                broker_name: "broker-a".to_string(),
+                ..Default::default()
+            })
+            .await;
+        assert!(results[0].is_ok());
+    }
+}
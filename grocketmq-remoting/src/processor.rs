@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use async_trait::async_trait;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::{
+    common::{code::ResponseCode, command::Command},
+    util::Error,
+};
+
+/// Handles requests received from a peer, like `NettyRequestProcessor` in Java.
+///
+/// Processors are registered by request code, on a client [`Channel`](crate::client::Channel)
+/// for requests the broker sends to its clients, and on a
+/// [`RemotingServer`](crate::server::RemotingServer).
+#[async_trait]
+pub trait RequestProcessor: Send + Sync {
+    /// Handles `request` and returns the response to write back, if any. The opaque and
+    /// the response flag of the returned command are set by the caller. An error is
+    /// answered with a `SystemError` response carrying its message as remark.
+    async fn process(
+        &self,
+        remote_addr: SocketAddr,
+        request: Command,
+    ) -> Result<Option<Command>, Error>;
+
+    /// Whether the processor is too busy to take requests right now.
+    fn reject_request(&self) -> bool {
+        false
+    }
+}
+
+/// Bounds how many requests the processors bound to it handle at once, like the
+/// executor a processor is registered with in Java. Requests wait for a free slot;
+/// once `max_pending` requests are waiting, further ones are answered with `SystemBusy`.
+#[derive(Debug)]
+pub struct Executor {
+    permits: Semaphore,
+    max_pending: usize,
+    pending: AtomicUsize,
+}
+
+impl Executor {
+    pub fn new(max_concurrency: usize, max_pending: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_concurrency),
+            max_pending,
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    /// An executor that never makes requests wait.
+    pub fn unbounded() -> Self {
+        Self::new(Semaphore::MAX_PERMITS, 0)
+    }
+
+    /// Waits for a slot, or returns `None` if too many requests are already waiting.
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        if let Ok(permit) = self.permits.try_acquire() {
+            return Some(permit);
+        }
+        if self.pending.fetch_add(1, Ordering::AcqRel) >= self.max_pending {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        let permit = self.permits.acquire().await.ok();
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+        permit
+    }
+}
+
+/// A processor together with the executor it runs on.
+#[derive(Clone)]
+pub(crate) struct Registration {
+    pub processor: Arc<dyn RequestProcessor>,
+    pub executor: Arc<Executor>,
+}
+
+/// Request processors keyed by request code.
+#[derive(Default)]
+pub(crate) struct ProcessorTable {
+    processors: RwLock<HashMap<i32, Registration>>,
+    default_processor: RwLock<Option<Registration>>,
+}
+
+impl ProcessorTable {
+    pub fn register(&self, code: i32, registration: Registration) {
+        self.processors.write().unwrap().insert(code, registration);
+    }
+
+    pub fn register_default(&self, registration: Registration) {
+        *self.default_processor.write().unwrap() = Some(registration);
+    }
+
+    /// The processor registered for `code`, falling back to the default processor.
+    pub fn get(&self, code: i32) -> Option<Registration> {
+        self.processors
+            .read()
+            .unwrap()
+            .get(&code)
+            .cloned()
+            .or_else(|| self.default_processor.read().unwrap().clone())
+    }
+}
+
+/// Runs `request` through the processor registered for it and prepares the response to
+/// write back: it carries the opaque and header format of the request, or is `None` for
+/// oneway requests.
+pub(crate) async fn process(
+    registration: Option<Registration>,
+    remote_addr: SocketAddr,
+    request: Command,
+) -> Option<Command> {
+    let code = request.code();
+    let opaque = request.opaque();
+    let oneway = request.is_oneway();
+    let serialize_type = request.serialize_type();
+
+    let response = match registration {
+        None => {
+            let mut response = Command::new_response(ResponseCode::RequestCodeNotSupported);
+            response.set_remark(format!(" request type {} not supported", code));
+            Some(response)
+        }
+        Some(registration) if registration.processor.reject_request() => {
+            let mut response = Command::new_response(ResponseCode::SystemBusy);
+            response.set_remark("[REJECTREQUEST]system busy, start flow control for a while");
+            Some(response)
+        }
+        Some(registration) => match registration.executor.acquire().await {
+            None => {
+                let mut response = Command::new_response(ResponseCode::SystemBusy);
+                response.set_remark("[OVERLOAD]system busy, start flow control for a while");
+                Some(response)
+            }
+            Some(_permit) => match registration.processor.process(remote_addr, request).await {
+                Ok(response) => response,
+                Err(e) => {
+                    let mut response = Command::new_response(ResponseCode::SystemError);
+                    response.set_remark(e.to_string());
+                    Some(response)
+                }
+            },
+        },
+    };
+    if oneway {
+        return None;
+    }
+
+    response.map(|mut response| {
+        response.mark_response_type();
+        response.set_opaque(opaque);
+        response.set_serialize_type(serialize_type);
+        response
+    })
+}
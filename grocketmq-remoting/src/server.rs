@@ -0,0 +1,475 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    select,
+    sync::{mpsc, watch},
+    task::{JoinHandle, JoinSet},
+    time::sleep,
+};
+
+use crate::{
+    common::frame::{self, BoxStream, CommandReader, CommandWriter, DEFAULT_MAX_FRAME_SIZE},
+    processor::{self, Executor, ProcessorTable, Registration, RequestProcessor},
+    util::Error,
+};
+
+/// The server side of the remoting protocol: accepts connections and answers their
+/// requests with the processors registered by request code.
+pub struct RemotingServer {
+    listener: TcpListener,
+    processors: Arc<ProcessorTable>,
+    public_executor: Arc<Executor>,
+    acceptor: Acceptor,
+    close_tx: Arc<watch::Sender<()>>,
+}
+
+/// Turns accepted TCP connections into framed halves requests are read from.
+#[derive(Clone)]
+struct Acceptor {
+    max_frame_size: usize,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsServerConfig>,
+}
+
+impl Acceptor {
+    async fn accept(&self, stream: TcpStream) -> Result<(CommandReader, CommandWriter), Error> {
+        let stream: BoxStream = Box::new(stream);
+        #[cfg(feature = "tls")]
+        let stream: BoxStream = match &self.tls {
+            Some(tls) => Box::new(tls.acceptor().accept(stream).await?),
+            None => stream,
+        };
+        Ok(frame::split(stream, self.max_frame_size))
+    }
+}
+
+/// Controls a started [`RemotingServer`]. Dropping the handle shuts the server down
+/// without waiting for it.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown_tx: watch::Sender<bool>,
+    close_tx: Arc<watch::Sender<()>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl RemotingServer {
+    pub async fn bind(addr: &str) -> Result<Self, Error> {
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|_| Error::InvalidAddress(addr.to_string()))?;
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self {
+            listener,
+            processors: Arc::new(ProcessorTable::default()),
+            public_executor: Arc::new(Executor::unbounded()),
+            acceptor: Acceptor {
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                #[cfg(feature = "tls")]
+                tls: None,
+            },
+            close_tx: Arc::new(watch::channel(()).0),
+        })
+    }
+
+    /// Sets the largest frame read from or written to a connection. Connections sending
+    /// a larger one are closed.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.acceptor.max_frame_size = max_frame_size;
+    }
+
+    /// Only accepts TLS connections.
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: crate::tls::TlsServerConfig) {
+        self.acceptor.tls = Some(tls);
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Registers the processor for requests with `code`. Processors registered without
+    /// an executor share the public one, which doesn't limit concurrency.
+    pub fn register_processor(
+        &self,
+        code: impl Into<i32>,
+        processor: Arc<dyn RequestProcessor>,
+        executor: Option<Arc<Executor>>,
+    ) {
+        let registration = self.registration(processor, executor);
+        self.processors.register(code.into(), registration);
+    }
+
+    /// Registers the processor for requests no other processor is registered for.
+    pub fn register_default_processor(
+        &self,
+        processor: Arc<dyn RequestProcessor>,
+        executor: Option<Arc<Executor>>,
+    ) {
+        let registration = self.registration(processor, executor);
+        self.processors.register_default(registration);
+    }
+
+    fn registration(
+        &self,
+        processor: Arc<dyn RequestProcessor>,
+        executor: Option<Arc<Executor>>,
+    ) -> Registration {
+        Registration {
+            processor,
+            executor: executor.unwrap_or_else(|| self.public_executor.clone()),
+        }
+    }
+
+    /// Sends the signal [`ServerHandle::close_connections`] sends, for processors that
+    /// drop connections on purpose.
+    #[cfg(feature = "mock")]
+    pub(crate) fn close_sender(&self) -> Arc<watch::Sender<()>> {
+        self.close_tx.clone()
+    }
+
+    /// Starts accepting connections in the background.
+    pub fn start(self) -> Result<ServerHandle, Error> {
+        let local_addr = self.local_addr()?;
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let accept_task = tokio::spawn(RemotingServer::accept(
+            self.listener,
+            self.acceptor,
+            self.processors,
+            shutdown_rx,
+            self.close_tx.subscribe(),
+        ));
+        Ok(ServerHandle {
+            local_addr,
+            shutdown_tx,
+            close_tx: self.close_tx,
+            accept_task,
+        })
+    }
+
+    async fn accept(
+        listener: TcpListener,
+        acceptor: Acceptor,
+        processors: Arc<ProcessorTable>,
+        mut shutdown_rx: watch::Receiver<bool>,
+        close_rx: watch::Receiver<()>,
+    ) {
+        let mut connections = JoinSet::new();
+        loop {
+            select! {
+                accepted = listener.accept() => {
+                    match accepted {
+                        Ok((stream, remote_addr)) => {
+                            let _ = stream.set_nodelay(true);
+                            let acceptor = acceptor.clone();
+                            let processors = processors.clone();
+                            let shutdown_rx = shutdown_rx.clone();
+                            let mut close_rx = close_rx.clone();
+                            close_rx.mark_unchanged();
+                            // Handshakes run in the connection task, so a slow client
+                            // doesn't hold up accepting others.
+                            connections.spawn(async move {
+                                if let Ok(stream) = acceptor.accept(stream).await {
+                                    RemotingServer::serve(
+                                        stream,
+                                        remote_addr,
+                                        processors,
+                                        shutdown_rx,
+                                        close_rx,
+                                    )
+                                    .await;
+                                }
+                            });
+                        }
+                        // Most likely out of file descriptors, give connections time to close.
+                        Err(_) => sleep(Duration::from_millis(100)).await,
+                    }
+                }
+                Some(_) = connections.join_next() => {}
+                _ = shutdown_rx.changed() => break,
+            }
+        }
+        drop(listener);
+        while connections.join_next().await.is_some() {}
+    }
+
+    /// Answers the requests of one connection until the peer closes it, or until the
+    /// server shuts down and every request read so far has been answered. Closing the
+    /// connections drops it right away instead.
+    async fn serve(
+        (mut reader, mut writer): (CommandReader, CommandWriter),
+        remote_addr: SocketAddr,
+        processors: Arc<ProcessorTable>,
+        mut shutdown_rx: watch::Receiver<bool>,
+        mut close_rx: watch::Receiver<()>,
+    ) {
+        let (reply_tx, mut reply_rx) = mpsc::unbounded_channel();
+        // Dropped on shutdown, so `reply_rx` ends once the last processor finished.
+        let mut reply_tx = Some(reply_tx);
+
+        loop {
+            select! {
+                request = reader.next(), if reply_tx.is_some() => {
+                    // Closed, or sent a frame that can't be decoded.
+                    let Some(Ok(request)) = request else {
+                        return;
+                    };
+                    if request.is_response() {
+                        continue;
+                    }
+                    let registration = processors.get(request.code());
+                    let reply_tx = reply_tx.clone();
+                    tokio::spawn(async move {
+                        if let Some(response) =
+                            processor::process(registration, remote_addr, request).await
+                        {
+                            if let Some(reply_tx) = reply_tx {
+                                let _ = reply_tx.send(response);
+                            }
+                        }
+                    });
+                }
+                reply = reply_rx.recv() => {
+                    let Some(reply) = reply else {
+                        break;
+                    };
+                    if frame::is_broken(&writer.send(reply).await) {
+                        return;
+                    }
+                }
+                _ = shutdown_rx.changed(), if reply_tx.is_some() => {
+                    reply_tx = None;
+                }
+                Ok(()) = close_rx.changed() => return,
+            }
+        }
+        let _ = writer.shutdown().await;
+    }
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Drops every open connection without answering the requests in flight, while
+    /// still accepting new ones, e.g. to test how clients reconnect.
+    pub fn close_connections(&self) {
+        self.close_tx.send_replace(());
+    }
+
+    /// Stops accepting connections and reading requests, waits until every request
+    /// already read has been answered, then closes all connections.
+    pub async fn shutdown(self) {
+        self.shutdown_tx.send_replace(true);
+        let _ = self.accept_task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::{
+        client::{Channel, ConnectionState},
+        common::{
+            code::{RequestCode, ResponseCode},
+            command::Command,
+        },
+    };
+
+    struct EchoProcessor;
+
+    #[async_trait]
+    impl RequestProcessor for EchoProcessor {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            request: Command,
+        ) -> Result<Option<Command>, Error> {
+            let mut response = Command::new_response(ResponseCode::Success);
+            if let Some(body) = request.body() {
+                response.set_body(body.clone());
+            }
+            Ok(Some(response))
+        }
+    }
+
+    /// Reports every request it received, then holds it until released.
+    struct BlockingProcessor {
+        entered_tx: mpsc::UnboundedSender<()>,
+        release: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl RequestProcessor for BlockingProcessor {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            _request: Command,
+        ) -> Result<Option<Command>, Error> {
+            let _ = self.entered_tx.send(());
+            self.release.notified().await;
+            Ok(Some(Command::new_response(ResponseCode::Success)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_processor(RequestCode::SendMessage, Arc::new(EchoProcessor), None);
+        let server = server.start().unwrap();
+        let channel = Channel::new(&server.local_addr().to_string())
+            .await
+            .unwrap();
+
+        let mut request = Command::new(RequestCode::SendMessage);
+        request.set_body(b"hello".to_vec());
+        let response = channel.request(request).await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+        assert_eq!(&b"hello"[..], response.body().unwrap());
+
+        let response = channel
+            .request(Command::new(RequestCode::PullMessage))
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(ResponseCode::RequestCodeNotSupported),
+            response.response_code()
+        );
+
+        channel
+            .send_oneway(Command::new(RequestCode::SendMessage))
+            .await
+            .unwrap();
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_default_processor() {
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_default_processor(Arc::new(EchoProcessor), None);
+        let server = server.start().unwrap();
+        let channel = Channel::new(&server.local_addr().to_string())
+            .await
+            .unwrap();
+
+        let response = channel
+            .request(Command::new(RequestCode::PullMessage))
+            .await
+            .unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_close_connections() {
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_default_processor(Arc::new(EchoProcessor), None);
+        let server = server.start().unwrap();
+        let channel = Channel::new(&server.local_addr().to_string())
+            .await
+            .unwrap();
+        let mut state_rx = channel.subscribe();
+        channel.request(Command::new(0)).await.unwrap();
+
+        server.close_connections();
+        state_rx
+            .wait_for(|state| *state == ConnectionState::Connecting)
+            .await
+            .unwrap();
+        // The server keeps accepting, so the channel reconnects.
+        state_rx
+            .wait_for(|state| *state == ConnectionState::Active)
+            .await
+            .unwrap();
+        let response = channel.request(Command::new(0)).await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_executor_overload() {
+        let release = Arc::new(Notify::new());
+        let (entered_tx, mut entered_rx) = mpsc::unbounded_channel();
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_processor(
+            RequestCode::SendMessage,
+            Arc::new(BlockingProcessor {
+                entered_tx,
+                release: release.clone(),
+            }),
+            Some(Arc::new(Executor::new(1, 0))),
+        );
+        let server = server.start().unwrap();
+        let channel = Channel::new(&server.local_addr().to_string())
+            .await
+            .unwrap();
+
+        let blocked = channel
+            .request_async(
+                Command::new(RequestCode::SendMessage),
+                Duration::from_secs(3),
+            )
+            .await
+            .unwrap();
+        // Wait until the first request holds the only slot.
+        entered_rx.recv().await.unwrap();
+        let response = channel
+            .request(Command::new(RequestCode::SendMessage))
+            .await
+            .unwrap();
+        assert_eq!(Some(ResponseCode::SystemBusy), response.response_code());
+
+        release.notify_one();
+        let response = blocked.await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let release = Arc::new(Notify::new());
+        let (entered_tx, mut entered_rx) = mpsc::unbounded_channel();
+        let server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.register_processor(
+            RequestCode::SendMessage,
+            Arc::new(BlockingProcessor {
+                entered_tx,
+                release: release.clone(),
+            }),
+            None,
+        );
+        let server = server.start().unwrap();
+        let channel = Channel::new(&server.local_addr().to_string())
+            .await
+            .unwrap();
+        let mut state_rx = channel.subscribe();
+
+        let in_flight = channel
+            .request_async(
+                Command::new(RequestCode::SendMessage),
+                Duration::from_secs(3),
+            )
+            .await
+            .unwrap();
+        entered_rx.recv().await.unwrap();
+
+        let shutdown = tokio::spawn(server.shutdown());
+        sleep(Duration::from_millis(50)).await;
+        assert!(!shutdown.is_finished());
+        release.notify_one();
+
+        let response = in_flight.await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+        shutdown.await.unwrap();
+        state_rx
+            .wait_for(|state| *state == ConnectionState::Connecting)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+//! TLS for channels and servers, like brokers running with `tls.enable=true`.
+
+use std::{fmt, fs, path::PathBuf, sync::Arc};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    server::WebPkiClientVerifier,
+    ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsAcceptor, TlsConnector};
+
+use crate::util::Error;
+
+/// PEM data, given directly or read from a file when the config is built.
+#[derive(Debug, Clone)]
+enum Pem {
+    Data(Vec<u8>),
+    File(PathBuf),
+}
+
+impl Pem {
+    fn read(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            Pem::Data(data) => Ok(data.clone()),
+            Pem::File(path) => fs::read(path).map_err(|e| {
+                Error::InvalidTlsConfig(format!("failed to read {}: {}", path.display(), e))
+            }),
+        }
+    }
+
+    fn certs(&self) -> Result<Vec<CertificateDer<'static>>, Error> {
+        let certs = rustls_pemfile::certs(&mut self.read()?.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidTlsConfig(format!("bad certificate: {}", e)))?;
+        if certs.is_empty() {
+            return Err(Error::InvalidTlsConfig("no certificate found".to_string()));
+        }
+        Ok(certs)
+    }
+
+    fn private_key(&self) -> Result<PrivateKeyDer<'static>, Error> {
+        rustls_pemfile::private_key(&mut self.read()?.as_slice())
+            .map_err(|e| Error::InvalidTlsConfig(format!("bad private key: {}", e)))?
+            .ok_or_else(|| Error::InvalidTlsConfig("no private key found".to_string()))
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, Error> {
+        let mut roots = RootCertStore::empty();
+        for cert in self.certs()? {
+            roots.add(cert).map_err(tls_error)?;
+        }
+        Ok(roots)
+    }
+}
+
+fn tls_error(e: rustls::Error) -> Error {
+    Error::InvalidTlsConfig(e.to_string())
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// TLS settings of a [`Channel`](crate::client::Channel), created with
+/// [`TlsClientConfig::builder`].
+#[derive(Clone)]
+pub struct TlsClientConfig {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl TlsClientConfig {
+    pub fn builder() -> TlsClientConfigBuilder {
+        TlsClientConfigBuilder::default()
+    }
+
+    /// Runs the handshake over `stream`. Without a configured server name, the host of
+    /// `addr` is sent as SNI and checked against the certificate.
+    pub(crate) async fn connect(
+        &self,
+        addr: &str,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, Error> {
+        let server_name = match &self.server_name {
+            Some(server_name) => server_name.clone(),
+            None => {
+                let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                ServerName::try_from(host.to_string())
+                    .map_err(|_| Error::InvalidAddress(addr.to_string()))?
+            }
+        };
+        Ok(self.connector.connect(server_name, stream).await?)
+    }
+}
+
+impl fmt::Debug for TlsClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsClientConfig")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TlsClientConfigBuilder {
+    ca: Option<Pem>,
+    identity: Option<(Pem, Pem)>,
+    server_name: Option<String>,
+    insecure_skip_verify: bool,
+}
+
+impl TlsClientConfigBuilder {
+    /// CA certificates the server certificate must be issued by, in PEM.
+    pub fn ca_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca = Some(Pem::Data(pem.into()));
+        self
+    }
+
+    pub fn ca_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca = Some(Pem::File(path.into()));
+        self
+    }
+
+    /// Certificate chain and private key presented to servers that require client
+    /// authentication, in PEM.
+    pub fn client_cert_pem(mut self, cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        self.identity = Some((Pem::Data(cert.into()), Pem::Data(key.into())));
+        self
+    }
+
+    pub fn client_cert_file(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.identity = Some((Pem::File(cert.into()), Pem::File(key.into())));
+        self
+    }
+
+    /// Name sent as SNI and checked against the server certificate, instead of the
+    /// host of the channel address.
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Accepts any server certificate. Only meant for tests against self-signed brokers.
+    pub fn insecure_skip_verify(mut self, skip: bool) -> Self {
+        self.insecure_skip_verify = skip;
+        self
+    }
+
+    pub fn build(self) -> Result<TlsClientConfig, Error> {
+        let provider = provider();
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = if self.insecure_skip_verify {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipVerify(provider)))
+        } else {
+            let ca = self
+                .ca
+                .ok_or_else(|| Error::InvalidTlsConfig("no CA certificates".to_string()))?;
+            builder.with_root_certificates(ca.root_store()?)
+        };
+        let config = match self.identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(cert.certs()?, key.private_key()?)
+                .map_err(tls_error)?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let server_name = self
+            .server_name
+            .map(|name| {
+                ServerName::try_from(name.clone())
+                    .map_err(|_| Error::InvalidTlsConfig(format!("invalid server name {}", name)))
+            })
+            .transpose()?;
+        Ok(TlsClientConfig {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+}
+
+/// TLS settings of a [`RemotingServer`](crate::server::RemotingServer), created with
+/// [`TlsServerConfig::builder`].
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    acceptor: TlsAcceptor,
+}
+
+impl TlsServerConfig {
+    /// A server presenting the certificate chain and private key, in PEM.
+    pub fn builder(cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> TlsServerConfigBuilder {
+        TlsServerConfigBuilder {
+            cert: Pem::Data(cert.into()),
+            key: Pem::Data(key.into()),
+            client_ca: None,
+        }
+    }
+
+    pub fn builder_from_files(
+        cert: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+    ) -> TlsServerConfigBuilder {
+        TlsServerConfigBuilder {
+            cert: Pem::File(cert.into()),
+            key: Pem::File(key.into()),
+            client_ca: None,
+        }
+    }
+
+    pub(crate) fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+}
+
+impl fmt::Debug for TlsServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsServerConfig").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct TlsServerConfigBuilder {
+    cert: Pem,
+    key: Pem,
+    client_ca: Option<Pem>,
+}
+
+impl TlsServerConfigBuilder {
+    /// Requires clients to present a certificate issued by one of these CAs, in PEM.
+    pub fn client_ca_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.client_ca = Some(Pem::Data(pem.into()));
+        self
+    }
+
+    pub fn client_ca_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.client_ca = Some(Pem::File(path.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<TlsServerConfig, Error> {
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = match self.client_ca {
+            Some(client_ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(client_ca.root_store()?),
+                    provider,
+                )
+                .build()
+                .map_err(|e| Error::InvalidTlsConfig(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(self.cert.certs()?, self.key.private_key()?)
+            .map_err(tls_error)?;
+        Ok(TlsServerConfig {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+}
+
+/// Accepts every server certificate, but still checks the handshake signatures.
+#[derive(Debug)]
+struct SkipVerify(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use async_trait::async_trait;
+    use rcgen::CertifiedKey;
+
+    use super::*;
+    use crate::{
+        client::{Channel, ChannelConfig, ConnectionState},
+        common::{
+            code::{RequestCode, ResponseCode},
+            command::Command,
+        },
+        processor::RequestProcessor,
+        server::{RemotingServer, ServerHandle},
+    };
+
+    struct SuccessProcessor;
+
+    #[async_trait]
+    impl RequestProcessor for SuccessProcessor {
+        async fn process(
+            &self,
+            _remote_addr: SocketAddr,
+            _request: Command,
+        ) -> Result<Option<Command>, Error> {
+            Ok(Some(Command::new_response(ResponseCode::Success)))
+        }
+    }
+
+    fn self_signed(name: &str) -> CertifiedKey {
+        rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap()
+    }
+
+    async fn start_server(tls: TlsServerConfig) -> ServerHandle {
+        let mut server = RemotingServer::bind("127.0.0.1:0").await.unwrap();
+        server.set_tls(tls);
+        server.register_default_processor(Arc::new(SuccessProcessor), None);
+        server.start().unwrap()
+    }
+
+    async fn connect(server: &ServerHandle, tls: TlsClientConfig) -> Channel {
+        let config = ChannelConfig::builder().tls(tls).build();
+        Channel::with_config(&server.local_addr().to_string(), config)
+            .await
+            .unwrap()
+    }
+
+    async fn heartbeat(channel: &Channel) -> Result<Command, Error> {
+        channel.request(Command::new(RequestCode::HeartBeat)).await
+    }
+
+    #[tokio::test]
+    async fn test_verify_server() {
+        let server_cert = self_signed("localhost");
+        let server = start_server(
+            TlsServerConfig::builder(server_cert.cert.pem(), server_cert.key_pair.serialize_pem())
+                .build()
+                .unwrap(),
+        )
+        .await;
+
+        let channel = connect(
+            &server,
+            TlsClientConfig::builder()
+                .ca_pem(server_cert.cert.pem())
+                .server_name("localhost")
+                .build()
+                .unwrap(),
+        )
+        .await;
+        let response = heartbeat(&channel).await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+
+        // Not issued for the IP the channel connects to.
+        let channel = connect(
+            &server,
+            TlsClientConfig::builder()
+                .ca_pem(server_cert.cert.pem())
+                .build()
+                .unwrap(),
+        )
+        .await;
+        let mut state_rx = channel.subscribe();
+        let active = tokio::time::timeout(
+            Duration::from_millis(300),
+            state_rx.wait_for(|state| *state == ConnectionState::Active),
+        )
+        .await;
+        assert!(active.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_insecure_skip_verify() {
+        let server_cert = self_signed("broker");
+        let server = start_server(
+            TlsServerConfig::builder(server_cert.cert.pem(), server_cert.key_pair.serialize_pem())
+                .build()
+                .unwrap(),
+        )
+        .await;
+
+        let channel = connect(
+            &server,
+            TlsClientConfig::builder()
+                .insecure_skip_verify(true)
+                .build()
+                .unwrap(),
+        )
+        .await;
+        let response = heartbeat(&channel).await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+    }
+
+    #[tokio::test]
+    async fn test_client_cert() {
+        let server_cert = self_signed("localhost");
+        let client_cert = self_signed("client");
+        let server = start_server(
+            TlsServerConfig::builder(server_cert.cert.pem(), server_cert.key_pair.serialize_pem())
+                .client_ca_pem(client_cert.cert.pem())
+                .build()
+                .unwrap(),
+        )
+        .await;
+
+        let channel = connect(
+            &server,
+            TlsClientConfig::builder()
+                .ca_pem(server_cert.cert.pem())
+                .server_name("localhost")
+                .client_cert_pem(client_cert.cert.pem(), client_cert.key_pair.serialize_pem())
+                .build()
+                .unwrap(),
+        )
+        .await;
+        let response = heartbeat(&channel).await.unwrap();
+        assert_eq!(Some(ResponseCode::Success), response.response_code());
+    }
+
+    #[test]
+    fn test_missing_ca() {
+        assert!(matches!(
+            TlsClientConfig::builder().build(),
+            Err(Error::InvalidTlsConfig(_))
+        ));
+    }
+}
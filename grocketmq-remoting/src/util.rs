@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 pub fn vec_to_u32(data: &[u8]) -> u32 {
@@ -11,12 +13,34 @@ pub fn vec_to_u32(data: &[u8]) -> u32 {
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("bad command data")]
-    DecodeCommandError,
+    #[error("bad command data: {0}")]
+    DecodeCommandError(String),
+    #[error("illegal message: {0}")]
+    MessageIllegal(String),
+    #[error("frame of {length} bytes exceeds the limit of {max} bytes")]
+    FrameTooLarge { length: usize, max: usize },
     #[error("io error")]
     IoError(#[from] std::io::Error),
     #[error("stream not ready")]
     StreamNotReady,
     #[error("invalid address {0}")]
     InvalidAddress(String),
+    #[error("unknown code {0}")]
+    UnknownCode(i32),
+    #[error("missing header field {0}")]
+    MissingHeaderField(&'static str),
+    #[error("invalid value {value} for header field {name}")]
+    InvalidHeaderField { name: &'static str, value: String },
+    #[error("request {opaque} timed out after {elapsed:?}")]
+    Timeout { opaque: i32, elapsed: Duration },
+    #[error("connection closed")]
+    ConnectionClosed,
+    #[error("too many requests queued on the channel")]
+    ChannelFull,
+    #[error("too many requests in flight on the channel")]
+    TooManyRequests,
+    #[error("request failed with code {code}: {remark}")]
+    RemoteError { code: i32, remark: String },
+    #[error("invalid tls config: {0}")]
+    InvalidTlsConfig(String),
 }